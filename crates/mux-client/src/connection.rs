@@ -3,9 +3,10 @@
 use crate::{
     constants,
     request::{Fwd, Request, SessionZeroCopy},
-    shutdown_mux_master::shutdown_mux_master_from,
+    shutdown_mux_master::{close_fwd_from, shutdown_mux_master_from, terminate_mux_master_from},
     utils::{serialize_u32, SliceExt},
-    Error, ErrorExt, EstablishedSession, Response, Result, Session, Socket,
+    Error, ErrorExt, EstablishedSession, EstablishedStdioForward, Response, Result, Session,
+    Socket,
 };
 
 use std::{
@@ -211,6 +212,18 @@ impl Connection {
         let term_len: u32 = term.get_len_as_u32()?;
         let cmd_len: u32 = cmd.get_len_as_u32()?;
 
+        let mut env_len: u32 = 0;
+        let serialized_env_lens = session
+            .env
+            .iter()
+            .map(|env| {
+                let len = env.get_len_as_u32()?;
+                // len of env + env
+                env_len += 4 + len;
+                Ok(serialize_u32(len))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
         let request = Request::NewSession {
             request_id,
             session: SessionZeroCopy {
@@ -227,14 +240,14 @@ impl Connection {
 
         request.serialize(&mut self.serializer)?;
         let serialized_header = self.serializer.create_header(
-            /* len of term */ 4 + term_len + /* len of cmd */ 4 + cmd_len,
+            /* len of term */ 4 + term_len + /* len of cmd */ 4 + cmd_len + env_len,
         )?;
 
         let serialized_cmd_len = serialize_u32(cmd_len);
         let serialized_term_len = serialize_u32(term_len);
 
         // Write them to self.raw_conn
-        let mut io_slices = [
+        let mut io_slices = vec![
             IoSlice::new(&serialized_header),
             IoSlice::new(&self.serializer.output),
             IoSlice::new(&serialized_term_len),
@@ -243,6 +256,11 @@ impl Connection {
             IoSlice::new(cmd),
         ];
 
+        for (serialized_env_len, env) in serialized_env_lens.iter().zip(session.env.iter()) {
+            io_slices.push(IoSlice::new(serialized_env_len));
+            io_slices.push(IoSlice::new(env.into_inner()));
+        }
+
         write_vectored_all(&mut self.raw_conn, &mut io_slices).await?;
 
         for fd in fds {
@@ -322,6 +340,111 @@ impl Connection {
         self.open_new_session(&session, fds).await
     }
 
+    /// Return session_id
+    async fn open_stdio_forward_impl(
+        &mut self,
+        host: &str,
+        port: u32,
+        fds: &[RawFd; 2],
+    ) -> Result<u32> {
+        use Response::*;
+
+        let request_id = self.get_request_id();
+
+        // Prepare to serialize
+        let connect_socket = Socket::TcpSocket {
+            port,
+            host: host.into(),
+        };
+        let (connect_addr, connect_port) = connect_socket.as_serializable();
+
+        let connect_addr_len: u32 = connect_addr.get_len_as_u32()?;
+
+        let request = Request::NewStdioFwd { request_id };
+
+        // Serialize
+        self.reset_serializer();
+
+        request.serialize(&mut self.serializer)?;
+        let serialized_header = self
+            .serializer
+            .create_header(/* len of host */ 4 + connect_addr_len + /* port */ 4)?;
+
+        let serialized_connect_addr_len = serialize_u32(connect_addr_len);
+        let serialized_connect_port = serialize_u32(connect_port);
+
+        // Write them to self.raw_conn
+        let mut io_slices = [
+            IoSlice::new(&serialized_header),
+            IoSlice::new(&self.serializer.output),
+            IoSlice::new(&serialized_connect_addr_len),
+            IoSlice::new(connect_addr.into_inner()),
+            IoSlice::new(&serialized_connect_port),
+        ];
+
+        write_vectored_all(&mut self.raw_conn, &mut io_slices).await?;
+
+        for fd in fds {
+            self.send_with_fds(&[*fd]).await?;
+        }
+
+        let session_id = match self.read_response().await? {
+            SessionOpened {
+                response_id,
+                session_id,
+            } => {
+                Self::check_response_id(request_id, response_id)?;
+                session_id
+            }
+            PermissionDenied {
+                response_id,
+                reason,
+            } => {
+                Self::check_response_id(request_id, response_id)?;
+                return Err(Error::PermissionDenied(reason));
+            }
+            Failure {
+                response_id,
+                reason,
+            } => {
+                Self::check_response_id(request_id, response_id)?;
+                return Err(Error::RequestFailure(reason));
+            }
+            response => {
+                return Err(Error::invalid_server_response(
+                    &"SessionOpened, PermissionDenied or Failure",
+                    &response,
+                ))
+            }
+        };
+
+        Result::Ok(session_id)
+    }
+
+    /// Forward stdin and stdout to `host:port` on remote, same as `ssh -W`.
+    ///
+    /// Consumes `self` since the ssh mux server closes the connection once
+    /// the forwarding is torn down.
+    ///
+    /// * `fds` - stdin and stdout, must be in blocking mode
+    pub async fn open_stdio_forward(
+        mut self,
+        host: &str,
+        port: u32,
+        fds: &[RawFd; 2],
+    ) -> Result<EstablishedStdioForward> {
+        let session_id = self.open_stdio_forward_impl(host, port, fds).await?;
+
+        // EstablishedStdioForward does not send any request
+        // It merely wait for the connection to be closed.
+        self.serializer.output = Vec::new();
+
+        Ok(EstablishedStdioForward {
+            conn: self,
+            session_id,
+        })
+    }
+
     async fn send_fwd_request(&mut self, request_id: u32, fwd: &Fwd<'_>) -> Result<()> {
         let (fwd_mode, listen_socket, connect_socket) = fwd.as_serializable();
         let (listen_addr, listen_port) = listen_socket.as_serializable();
@@ -429,12 +552,16 @@ impl Connection {
     }
 
     /// Request for local/remote port forwarding.
+    ///
+    /// Return the port allocated by the remote if `forward_type` is
+    /// [`ForwardType::Remote`] and `listen_socket` is
+    /// [`Socket::TcpSocket`] with `port` set to `0`, otherwise `None`.
     pub async fn request_port_forward(
         &mut self,
         forward_type: ForwardType,
         listen_socket: &Socket<'_>,
         connect_socket: &Socket<'_>,
-    ) -> Result<()> {
+    ) -> Result<Option<NonZeroU32>> {
         use ForwardType::*;
         use Response::*;
 
@@ -453,7 +580,19 @@ impl Connection {
         self.send_fwd_request(request_id, &fwd).await?;
 
         match self.read_response().await? {
-            Ok { response_id } => Self::check_response_id(request_id, response_id),
+            Ok { response_id } => {
+                Self::check_response_id(request_id, response_id)?;
+                Result::Ok(None)
+            }
+            RemotePort {
+                response_id,
+                remote_port,
+            } => {
+                Self::check_response_id(request_id, response_id)?;
+                NonZeroU32::new(remote_port)
+                    .ok_or(Error::InvalidPort)
+                    .map(Some)
+            }
             PermissionDenied {
                 response_id,
                 reason,
@@ -469,7 +608,7 @@ impl Connection {
                 Err(Error::RequestFailure(reason))
             }
             response => Err(Error::invalid_server_response(
-                &"Ok, PermissionDenied or Failure",
+                &"Ok, RemotePort, PermissionDenied or Failure",
                 &response,
             )),
         }
@@ -483,7 +622,6 @@ impl Connection {
         connect_socket: &Socket<'_>,
     ) -> Result<()> {
         use ForwardType::*;
-        use Response::*;
 
         let fwd = match forward_type {
             Local => Fwd::Local {
@@ -496,8 +634,14 @@ impl Connection {
             },
         };
 
+        self.close_fwd(&fwd).await
+    }
+
+    pub(crate) async fn close_fwd(&mut self, fwd: &Fwd<'_>) -> Result<()> {
+        use Response::*;
+
         let request_id = self.get_request_id();
-        self.send_close_fwd_request(request_id, &fwd).await?;
+        self.send_close_fwd_request(request_id, fwd).await?;
 
         match self.read_response().await? {
             Ok { response_id } => Self::check_response_id(request_id, response_id),
@@ -597,6 +741,72 @@ impl Connection {
         }
     }
 
+    /// Turn this connection into a proxy that speaks the ssh connection
+    /// protocol (`ssh -O proxy`), so that multiple channels can be opened
+    /// over this connection without passing any fd.
+    ///
+    /// * `reusable_io_slice_cap` - passed to [`ProxyClientBuilder::reusable_io_slice_cap`].
+    ///
+    /// [`ProxyClientBuilder::reusable_io_slice_cap`]: openssh_proxy_client::ProxyClientBuilder::reusable_io_slice_cap
+    #[cfg(feature = "proxy-client")]
+    pub async fn into_proxy(
+        self,
+        reusable_io_slice_cap: std::num::NonZeroUsize,
+    ) -> Result<openssh_proxy_client::ProxyClient> {
+        self.into_proxy_with_builder(
+            openssh_proxy_client::ProxyClient::builder()
+                .reusable_io_slice_cap(reusable_io_slice_cap),
+        )
+        .await
+    }
+
+    /// Same as [`Connection::into_proxy`], except that the [`ProxyClient`]
+    /// is created by `builder`, e.g. to enable keepalive.
+    ///
+    /// [`ProxyClient`]: openssh_proxy_client::ProxyClient
+    #[cfg(feature = "proxy-client")]
+    pub async fn into_proxy_with_builder(
+        mut self,
+        builder: &openssh_proxy_client::ProxyClientBuilder,
+    ) -> Result<openssh_proxy_client::ProxyClient> {
+        use tokio::io::AsyncReadExt;
+        use Response::*;
+
+        let request_id = self.get_request_id();
+        self.write(&Request::Proxy { request_id }).await?;
+
+        match self.read_response().await? {
+            Proxy { response_id } => Self::check_response_id(request_id, response_id)?,
+            PermissionDenied {
+                response_id,
+                reason,
+            } => {
+                Self::check_response_id(request_id, response_id)?;
+                return Err(Error::PermissionDenied(reason));
+            }
+            Failure {
+                response_id,
+                reason,
+            } => {
+                Self::check_response_id(request_id, response_id)?;
+                return Err(Error::RequestFailure(reason));
+            }
+            response => {
+                return Err(Error::invalid_server_response(
+                    &"Proxy, PermissionDenied or Failure",
+                    &response,
+                ))
+            }
+        }
+
+        let (rx, tx) = self.raw_conn.into_split();
+
+        // Any bytes read after the response belong to the proxy client.
+        let rx = io::Cursor::new(self.read_buffer).chain(rx);
+
+        Ok(builder.build(rx, tx))
+    }
+
     /// Request the master to stop accepting new multiplexing requests
     /// and remove its listener socket.
     ///
@@ -604,12 +814,68 @@ impl Connection {
     pub fn request_stop_listening_sync(self) -> Result<()> {
         shutdown_mux_master_from(self.raw_conn.into_std()?)
     }
+
+    /// Request the master to terminate immediately (`ssh -O exit`).
+    ///
+    /// The master might terminate before replying, in which case this
+    /// function would still return `Ok(())`.
+    pub async fn request_terminate(mut self) -> Result<()> {
+        use Response::*;
+
+        let request_id = self.get_request_id();
+        self.write(&Request::Terminate { request_id }).await?;
+
+        let response = match self.read_response().await {
+            Result::Ok(response) => response,
+            Err(Error::IOError(io_err)) if io_err.kind() == io::ErrorKind::UnexpectedEof => {
+                return Result::Ok(())
+            }
+            Err(err) => return Err(err),
+        };
+
+        match response {
+            Ok { response_id } => {
+                Self::check_response_id(request_id, response_id)?;
+                Result::Ok(())
+            }
+            PermissionDenied {
+                response_id,
+                reason,
+            } => {
+                Self::check_response_id(request_id, response_id)?;
+                Err(Error::PermissionDenied(reason))
+            }
+            Failure {
+                response_id,
+                reason,
+            } => {
+                Self::check_response_id(request_id, response_id)?;
+                Err(Error::RequestFailure(reason))
+            }
+            response => Err(Error::invalid_server_response(
+                &"Ok, PermissionDenied or Failure",
+                &response,
+            )),
+        }
+    }
+
+    /// Request the master to terminate immediately (`ssh -O exit`).
+    ///
+    /// **Only suitable to use in `Drop::drop`.**
+    pub fn request_terminate_sync(self) -> Result<()> {
+        terminate_mux_master_from(self.raw_conn.into_std()?)
+    }
+
+    /// **Only suitable to use in `Drop::drop`.**
+    pub(crate) fn close_fwd_sync(self, fwd: &Fwd<'_>) -> Result<()> {
+        close_fwd_from(self.raw_conn.into_std()?, fwd)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::SessionStatus;
+    use crate::{ForwardGuard, ForwardRegistry, SessionStatus};
 
     use std::convert::TryInto;
     use std::env;
@@ -718,6 +984,44 @@ mod tests {
     }
     run_test!(test_unordered_open_new_session, test_open_new_session_impl);
 
+    async fn test_open_new_session_with_env_impl(conn: Connection) {
+        let env = [
+            Cow::Borrowed("OPENSSH_MUX_CLIENT_TEST0=hello".try_into().unwrap()),
+            Cow::Borrowed("OPENSSH_MUX_CLIENT_TEST1=world".try_into().unwrap()),
+        ];
+        let session = Session::builder()
+            .cmd(Cow::Borrowed(
+                r#"test "$OPENSSH_MUX_CLIENT_TEST0 $OPENSSH_MUX_CLIENT_TEST1" = "hello world""#
+                    .try_into()
+                    .unwrap(),
+            ))
+            .env(Cow::Borrowed(&env))
+            .build();
+
+        let established_session = conn
+            .open_new_session(
+                &session,
+                &[
+                    io::stdin().as_raw_fd(),
+                    io::stdout().as_raw_fd(),
+                    io::stderr().as_raw_fd(),
+                ],
+            )
+            .await
+            .unwrap();
+
+        let session_status = established_session.wait().await.unwrap();
+        assert_matches!(
+            session_status,
+            SessionStatus::Exited { exit_value, .. }
+                if exit_value.unwrap() == 0
+        );
+    }
+    run_test!(
+        test_unordered_open_new_session_with_env,
+        test_open_new_session_with_env_impl
+    );
+
     async fn test_remote_socket_forward_impl(mut conn0: Connection, mut conn1: Connection) {
         let path = Path::new("/tmp/openssh-remote-forward.socket");
 
@@ -785,6 +1089,62 @@ mod tests {
         test_remote_socket_forward_impl
     );
 
+    async fn test_remote_dynamically_allocated_port_forward_impl(mut conn0: Connection) {
+        let output_listener = TcpListener::bind(("127.0.0.1", 1236)).await.unwrap();
+
+        eprintln!("Requesting port forward");
+        let remote_port = conn0
+            .request_port_forward(
+                ForwardType::Remote,
+                &Socket::TcpSocket {
+                    port: 0,
+                    host: "127.0.0.1".into(),
+                },
+                &Socket::TcpSocket {
+                    port: 1236,
+                    host: "127.0.0.1".into(),
+                },
+            )
+            .await
+            .unwrap()
+            .expect("Remote should allocate a port");
+
+        eprintln!("Creating remote process");
+        let cmd = format!(
+            "/usr/bin/socat OPEN:/data,rdonly TCP:127.0.0.1:{}",
+            remote_port
+        );
+        let (established_session, stdios) = create_remote_process(conn0, &cmd).await;
+
+        eprintln!("Waiting for connection");
+        let (mut output, _addr) = output_listener.accept().await.unwrap();
+
+        eprintln!("Reading");
+
+        const DATA: &[u8] = "0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n".as_bytes();
+
+        let mut buffer = [0_u8; DATA.len()];
+        output.read_exact(&mut buffer).await.unwrap();
+
+        assert_eq!(DATA, &buffer);
+
+        drop(output_listener);
+        drop(output);
+        drop(stdios);
+
+        eprintln!("Waiting for session to end");
+        let session_status = established_session.wait().await.unwrap();
+        assert_matches!(
+            session_status,
+            SessionStatus::Exited { exit_value, .. }
+                if exit_value.unwrap() == 0
+        );
+    }
+    run_test!(
+        test_unordered_remote_dynamically_allocated_port_forward,
+        test_remote_dynamically_allocated_port_forward_impl
+    );
+
     async fn test_local_socket_forward_impl(conn0: Connection, mut conn1: Connection) {
         let path: Cow<'_, _> = Path::new("/tmp/openssh-local-forward.socket").into();
 
@@ -850,10 +1210,105 @@ mod tests {
         test_local_socket_forward_impl
     );
 
-    async fn test_request_stop_listening_impl(mut conn: Connection) {
-        conn.request_stop_listening().await.unwrap();
+    async fn test_stdio_forward_impl(conn: Connection) {
+        // pipe() returns (PipeRead, PipeWrite)
+        let (stdin_read, stdin_write) = pipe().unwrap();
+        let (mut stdout_read, stdout_write) = pipe().unwrap();
+
+        eprintln!("Opening stdio forward to sshd");
+        let established_stdio_forward = conn
+            .open_stdio_forward(
+                "127.0.0.1",
+                22,
+                &[stdin_read.as_raw_fd(), stdout_write.as_raw_fd()],
+            )
+            .await
+            .unwrap();
 
-        eprintln!("Verify that existing connection is still usable.");
+        drop(stdin_read);
+        drop(stdout_write);
+
+        eprintln!("Reading ssh version exchange");
+        const BANNER: &[u8] = b"SSH-2.0-";
+        let mut buffer = [0_u8; BANNER.len()];
+        stdout_read.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(BANNER, &buffer);
+
+        drop(stdin_write);
+        drop(stdout_read);
+
+        eprintln!("Waiting for stdio forward to end");
+        established_stdio_forward.wait().await.unwrap();
+    }
+    run_test!(test_unordered_stdio_forward, test_stdio_forward_impl);
+
+    async fn test_forward_guard_impl(conn0: Connection, conn1: Connection) {
+        async fn check_forwarded_sshd(port: u16) {
+            let mut output = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+
+            const BANNER: &[u8] = b"SSH-2.0-";
+            let mut buffer = [0_u8; BANNER.len()];
+            output.read_exact(&mut buffer).await.unwrap();
+            assert_eq!(BANNER, &buffer);
+        }
+
+        let sshd_socket = Socket::TcpSocket {
+            port: 22,
+            host: "127.0.0.1".into(),
+        };
+
+        eprintln!("Requesting port forward");
+        let forward_guard = ForwardGuard::request_port_forward(
+            conn0,
+            ForwardType::Local,
+            Socket::TcpSocket {
+                port: 1237,
+                host: "127.0.0.1".into(),
+            },
+            sshd_socket.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(forward_guard.forward_type(), Some(ForwardType::Local));
+        assert_eq!(forward_guard.connect_socket(), Some(&sshd_socket));
+
+        check_forwarded_sshd(1237).await;
+
+        eprintln!("Closing port forward");
+        forward_guard.close().await.unwrap();
+        assert_matches!(TcpStream::connect(("127.0.0.1", 1237)).await, Err(_));
+
+        eprintln!("Requesting port forward");
+        let listen_socket = Socket::TcpSocket {
+            port: 1238,
+            host: "127.0.0.1".into(),
+        };
+        let mut registry = ForwardRegistry::new();
+        registry.register(
+            ForwardGuard::request_port_forward(
+                conn1,
+                ForwardType::Local,
+                listen_socket.clone(),
+                sshd_socket,
+            )
+            .await
+            .unwrap(),
+        );
+        assert_eq!(registry.list().len(), 1);
+        assert_eq!(registry.list()[0].listen_socket(), &listen_socket);
+
+        check_forwarded_sshd(1238).await;
+
+        eprintln!("Dropping registry of port forwards");
+        drop(registry);
+        assert_matches!(TcpStream::connect(("127.0.0.1", 1238)).await, Err(_));
+    }
+    run_test2!(test_unordered_forward_guard, test_forward_guard_impl);
+
+    async fn test_request_stop_listening_impl(mut conn: Connection) {
+        conn.request_stop_listening().await.unwrap();
+
+        eprintln!("Verify that existing connection is still usable.");
         test_open_new_session_impl(conn).await;
 
         eprintln!(
@@ -866,4 +1321,754 @@ mod tests {
         test_request_stop_listening,
         test_request_stop_listening_impl
     );
+
+    async fn test_request_terminate_impl(conn: Connection) {
+        conn.request_terminate().await.unwrap();
+
+        eprintln!("Verify that the multiplex server is indeed terminated.");
+        sleep(Duration::from_secs(1)).await;
+        assert_matches!(Connection::connect(PATH).await, Err(_));
+    }
+    run_test!(test_request_terminate, test_request_terminate_impl);
+
+    #[cfg(feature = "proxy-client")]
+    async fn test_into_proxy_impl(conn: Connection) {
+        use std::num::NonZeroUsize;
+
+        let proxy_client = conn
+            .into_proxy(NonZeroUsize::new(16).unwrap())
+            .await
+            .unwrap();
+
+        let session = proxy_client.open_session().await.unwrap();
+        let mut child = session
+            .exec(Cow::Borrowed("/bin/cat".try_into().unwrap()))
+            .await
+            .unwrap();
+
+        const DATA: &[u8] = b"0134131dqwdqdx13as\n";
+
+        {
+            let stdin = child.stdin.take().unwrap();
+            tokio::pin!(stdin);
+
+            stdin.write_all(DATA).await.unwrap();
+            stdin.flush().await.unwrap();
+        }
+
+        let mut stdout = child.stdout.take().unwrap();
+        let mut buffer = Vec::new();
+        stdout.read_to_end(&mut buffer).await.unwrap();
+        assert_eq!(DATA, &*buffer);
+
+        drop(stdout);
+
+        let exit_status = child.wait().await.unwrap();
+        assert_matches!(
+            exit_status,
+            Some(openssh_proxy_client::ExitStatus::Exited(0))
+        );
+
+        proxy_client.close().await.unwrap();
+    }
+    #[cfg(feature = "proxy-client")]
+    run_test!(test_unordered_into_proxy, test_into_proxy_impl);
+
+    #[cfg(feature = "proxy-client")]
+    async fn test_proxy_stderr_impl(conn: Connection) {
+        use std::num::NonZeroUsize;
+
+        let proxy_client = conn
+            .into_proxy(NonZeroUsize::new(16).unwrap())
+            .await
+            .unwrap();
+
+        let mut session = proxy_client.open_session().await.unwrap();
+
+        // stderr is always delivered to `Child::stderr`.
+        assert!(session.register_extended_data(1).is_none());
+
+        // sshd never sends this type, so it just sees eof.
+        let mut registered = session.register_extended_data(2).unwrap();
+        assert!(session.register_extended_data(2).is_none());
+
+        let mut child = session
+            .exec(Cow::Borrowed("echo err >&2".try_into().unwrap()))
+            .await
+            .unwrap();
+
+        let mut stdout = child.stdout.take().unwrap();
+        let mut stderr = child.stderr.take().unwrap();
+
+        let mut buffer = Vec::new();
+        stderr.read_to_end(&mut buffer).await.unwrap();
+        assert_eq!(b"err\n", &*buffer);
+
+        buffer.clear();
+        stdout.read_to_end(&mut buffer).await.unwrap();
+        registered.read_to_end(&mut buffer).await.unwrap();
+        assert!(buffer.is_empty());
+
+        drop((stdout, stderr, registered));
+
+        assert_matches!(
+            child.wait().await.unwrap(),
+            Some(openssh_proxy_client::ExitStatus::Exited(0))
+        );
+
+        proxy_client.close().await.unwrap();
+    }
+    #[cfg(feature = "proxy-client")]
+    run_test!(test_unordered_proxy_stderr, test_proxy_stderr_impl);
+
+    #[cfg(feature = "proxy-client")]
+    async fn test_proxy_pty_impl(conn: Connection) {
+        use openssh_proxy_client::{TerminalModes, WindowSize};
+        use std::num::NonZeroUsize;
+
+        let proxy_client = conn
+            .into_proxy(NonZeroUsize::new(16).unwrap())
+            .await
+            .unwrap();
+
+        let mut session = proxy_client.open_session().await.unwrap();
+        session
+            .request_pty(
+                Cow::Borrowed("xterm"),
+                WindowSize {
+                    width_chars: 80,
+                    height_rows: 24,
+                    ..Default::default()
+                },
+                &TerminalModes::new(),
+            )
+            .await
+            .unwrap();
+
+        let mut child = session
+            .exec(Cow::Borrowed(
+                "stty -echo; stty size; read line; stty size"
+                    .try_into()
+                    .unwrap(),
+            ))
+            .await
+            .unwrap();
+
+        let mut stdout = child.stdout.take().unwrap();
+
+        // Wait for the initial size to be printed, so that the window
+        // change cannot race with the first `stty size`.
+        let mut buffer = [0_u8; 7];
+        stdout.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(b"24 80\r\n", &buffer);
+
+        child
+            .window_change(WindowSize {
+                width_chars: 100,
+                height_rows: 30,
+                ..Default::default()
+            })
+            .unwrap();
+
+        {
+            let stdin = child.stdin.take().unwrap();
+            tokio::pin!(stdin);
+
+            stdin.write_all(b"\n").await.unwrap();
+            stdin.flush().await.unwrap();
+        }
+
+        let mut buffer = Vec::new();
+        stdout.read_to_end(&mut buffer).await.unwrap();
+        assert_eq!(b"30 100\r\n", &*buffer);
+
+        drop(stdout);
+
+        let exit_status = child.wait().await.unwrap();
+        assert_matches!(
+            exit_status,
+            Some(openssh_proxy_client::ExitStatus::Exited(0))
+        );
+
+        proxy_client.close().await.unwrap();
+    }
+    #[cfg(feature = "proxy-client")]
+    run_test!(test_unordered_proxy_pty, test_proxy_pty_impl);
+
+    #[cfg(feature = "proxy-client")]
+    async fn test_proxy_signal_impl(conn: Connection) {
+        use openssh_proxy_client::{ExitStatus, SignalName};
+        use std::num::NonZeroUsize;
+
+        let proxy_client = conn
+            .into_proxy(NonZeroUsize::new(16).unwrap())
+            .await
+            .unwrap();
+
+        let session = proxy_client.open_session().await.unwrap();
+        let child = session
+            .exec(Cow::Borrowed("exec sleep 1000".try_into().unwrap()))
+            .await
+            .unwrap();
+
+        sleep(Duration::from_secs(1)).await;
+
+        child.signal(SignalName::Term).unwrap();
+
+        let exit_status = child.wait().await.unwrap();
+        assert_matches!(
+            exit_status,
+            Some(ExitStatus::Killed(exit_signal))
+                if exit_signal.signal_name == SignalName::Term
+        );
+
+        proxy_client.close().await.unwrap();
+    }
+    #[cfg(feature = "proxy-client")]
+    run_test!(test_unordered_proxy_signal, test_proxy_signal_impl);
+
+    #[cfg(feature = "proxy-client")]
+    async fn test_proxy_keepalive_impl(conn: Connection) {
+        use std::num::NonZeroU32;
+
+        let proxy_client = conn
+            .into_proxy_with_builder(
+                openssh_proxy_client::ProxyClient::builder()
+                    .keepalive(Duration::from_millis(100), NonZeroU32::new(3).unwrap()),
+            )
+            .await
+            .unwrap();
+
+        // sshd replies to keepalive@openssh.com, so the connection
+        // must stay alive.
+        sleep(Duration::from_secs(1)).await;
+
+        let session = proxy_client.open_session().await.unwrap();
+        let child = session
+            .exec(Cow::Borrowed("true".try_into().unwrap()))
+            .await
+            .unwrap();
+
+        assert_matches!(
+            child.wait().await.unwrap(),
+            Some(openssh_proxy_client::ExitStatus::Exited(0))
+        );
+
+        proxy_client.close().await.unwrap();
+    }
+    #[cfg(feature = "proxy-client")]
+    run_test!(test_unordered_proxy_keepalive, test_proxy_keepalive_impl);
+
+    #[cfg(feature = "proxy-client")]
+    async fn test_proxy_max_channels_impl(conn: Connection) {
+        use std::num::NonZeroU32;
+        use tokio::time::timeout;
+
+        let proxy_client = conn
+            .into_proxy_with_builder(
+                openssh_proxy_client::ProxyClient::builder()
+                    .max_channels(NonZeroU32::new(2).unwrap()),
+            )
+            .await
+            .unwrap();
+
+        let mut children = Vec::new();
+        for _ in 0..2 {
+            let session = proxy_client.open_session().await.unwrap();
+            children.push(
+                session
+                    .exec(Cow::Borrowed("true".try_into().unwrap()))
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        // The limit is reached, so opening a new channel has to wait.
+        timeout(Duration::from_millis(100), proxy_client.open_session())
+            .await
+            .unwrap_err();
+
+        for child in children {
+            assert_matches!(
+                child.wait().await.unwrap(),
+                Some(openssh_proxy_client::ExitStatus::Exited(0))
+            );
+        }
+
+        // Channels are closed, so it can proceed now.
+        let session = proxy_client.open_session().await.unwrap();
+        let child = session
+            .exec(Cow::Borrowed("true".try_into().unwrap()))
+            .await
+            .unwrap();
+        assert_matches!(
+            child.wait().await.unwrap(),
+            Some(openssh_proxy_client::ExitStatus::Exited(0))
+        );
+
+        proxy_client.close().await.unwrap();
+    }
+    #[cfg(feature = "proxy-client")]
+    run_test!(
+        test_unordered_proxy_max_channels,
+        test_proxy_max_channels_impl
+    );
+
+    #[cfg(feature = "proxy-client")]
+    async fn test_proxy_close_with_impl(conn: Connection) {
+        use std::{num::NonZeroUsize, time::Instant};
+
+        let proxy_client = conn
+            .into_proxy(NonZeroUsize::new(16).unwrap())
+            .await
+            .unwrap();
+
+        let session = proxy_client.open_session().await.unwrap();
+        let mut child = session
+            .exec(Cow::Borrowed("sleep 1000".try_into().unwrap()))
+            .await
+            .unwrap();
+        let channel_id = child.channel_id();
+
+        // sshd does not close the channel until the process exits,
+        // which never happens before the deadline.
+        let start = Instant::now();
+        let aborted = proxy_client
+            .close_with(Instant::now() + Duration::from_secs(2))
+            .await
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_secs(2));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(aborted, [channel_id]);
+
+        // The channel is closed, so writing to it must fail.
+        let stdin = child.stdin.take().unwrap();
+        tokio::pin!(stdin);
+        stdin.write_all(b"data").await.unwrap_err();
+
+        assert_matches!(
+            child.wait().await,
+            Err(openssh_proxy_client::Error::IOError(err))
+                if err.kind() == io::ErrorKind::ConnectionAborted
+        );
+    }
+    #[cfg(feature = "proxy-client")]
+    run_test!(test_unordered_proxy_close_with, test_proxy_close_with_impl);
+
+    #[cfg(feature = "proxy-client")]
+    async fn test_proxy_close_with_drained_impl(conn: Connection) {
+        use std::{num::NonZeroUsize, time::Instant};
+
+        let proxy_client = conn
+            .into_proxy(NonZeroUsize::new(16).unwrap())
+            .await
+            .unwrap();
+
+        let session = proxy_client.open_session().await.unwrap();
+        let mut child = session
+            .exec(Cow::Borrowed("sleep 1".try_into().unwrap()))
+            .await
+            .unwrap();
+
+        // stdin is held open, but the process exits by itself well
+        // before the deadline, so the channel is drained.
+        let _stdin = child.stdin.take().unwrap();
+
+        let start = Instant::now();
+        let aborted = proxy_client
+            .close_with(Instant::now() + Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(aborted, []);
+    }
+    #[cfg(feature = "proxy-client")]
+    run_test!(
+        test_unordered_proxy_close_with_drained,
+        test_proxy_close_with_drained_impl
+    );
+
+    #[cfg(feature = "proxy-client")]
+    async fn test_proxy_channel_config_impl(conn: Connection) {
+        use openssh_proxy_client::ChannelConfig;
+        use std::num::{NonZeroU32, NonZeroUsize};
+
+        let proxy_client = conn
+            .into_proxy(NonZeroUsize::new(16).unwrap())
+            .await
+            .unwrap();
+
+        let mut config = ChannelConfig::new();
+        config
+            .window_size(NonZeroU32::new(4096).unwrap())
+            .max_packet_size(NonZeroU32::new(1024).unwrap())
+            .adaptive_window(NonZeroU32::new(1024 * 1024).unwrap());
+
+        let session = proxy_client
+            .open_session_with_config(&config)
+            .await
+            .unwrap();
+        let mut child = session
+            .exec(Cow::Borrowed(
+                "head -c 4194304 /dev/zero".try_into().unwrap(),
+            ))
+            .await
+            .unwrap();
+
+        let mut stdout = child.stdout.take().unwrap();
+        let mut buffer = Vec::new();
+        stdout.read_to_end(&mut buffer).await.unwrap();
+        assert_eq!(buffer.len(), 4194304);
+        assert!(buffer.iter().all(|byte| *byte == 0));
+
+        drop(stdout);
+
+        assert_matches!(
+            child.wait().await.unwrap(),
+            Some(openssh_proxy_client::ExitStatus::Exited(0))
+        );
+
+        proxy_client.close().await.unwrap();
+    }
+    #[cfg(feature = "proxy-client")]
+    run_test!(
+        test_unordered_proxy_channel_config,
+        test_proxy_channel_config_impl
+    );
+
+    #[cfg(feature = "proxy-client")]
+    async fn test_proxy_slow_reader_impl(conn: Connection) {
+        use openssh_proxy_client::ChannelConfig;
+        use std::{
+            num::{NonZeroU32, NonZeroUsize},
+            time::Duration,
+        };
+        use tokio::time::sleep;
+
+        let proxy_client = conn
+            .into_proxy(NonZeroUsize::new(16).unwrap())
+            .await
+            .unwrap();
+
+        let mut config = ChannelConfig::new();
+        config
+            .window_size(NonZeroU32::new(4096).unwrap())
+            .max_packet_size(NonZeroU32::new(1024).unwrap());
+
+        let session = proxy_client
+            .open_session_with_config(&config)
+            .await
+            .unwrap();
+        let mut child = session
+            .exec(Cow::Borrowed("head -c 65536 /dev/zero".try_into().unwrap()))
+            .await
+            .unwrap();
+
+        let mut stdout = child.stdout.take().unwrap();
+
+        // Read in small chunks with delay, so that sshd has to wait
+        // for the window to be extended as the data is consumed.
+        let mut buffer = [1_u8; 512];
+        let mut total = 0;
+        loop {
+            let n = stdout.read(&mut buffer).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            assert!(buffer[..n].iter().all(|byte| *byte == 0));
+            total += n;
+
+            sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!(total, 65536);
+
+        drop(stdout);
+
+        assert_matches!(
+            child.wait().await.unwrap(),
+            Some(openssh_proxy_client::ExitStatus::Exited(0))
+        );
+
+        proxy_client.close().await.unwrap();
+    }
+    #[cfg(feature = "proxy-client")]
+    run_test!(
+        test_unordered_proxy_slow_reader,
+        test_proxy_slow_reader_impl
+    );
+
+    #[cfg(feature = "proxy-client")]
+    async fn test_proxy_agent_forwarding_impl(conn: Connection) {
+        use std::num::NonZeroUsize;
+        use tokio::sync::mpsc::unbounded_channel;
+
+        let proxy_client = conn
+            .into_proxy(NonZeroUsize::new(16).unwrap())
+            .await
+            .unwrap();
+
+        let (sender, mut receiver) = unbounded_channel();
+        proxy_client.set_agent_forwarding_handler(move |stream| {
+            sender.send(stream).unwrap();
+        });
+
+        let mut session = proxy_client.open_session().await.unwrap();
+        session.request_agent_forwarding().await.unwrap();
+
+        let child = session
+            .exec(Cow::Borrowed(
+                "socat -u OPEN:/data UNIX-CONNECT:\"$SSH_AUTH_SOCK\" >/dev/stderr"
+                    .try_into()
+                    .unwrap(),
+            ))
+            .await
+            .unwrap();
+
+        eprintln!("Accepting auth-agent@openssh.com channel");
+        {
+            let stream = receiver.recv().await.unwrap();
+            tokio::pin!(stream);
+
+            const DATA: &[u8] = "0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n".as_bytes();
+            let mut buffer = Vec::new();
+            stream.read_to_end(&mut buffer).await.unwrap();
+
+            assert_eq!(DATA, &*buffer);
+        }
+
+        let exit_status = child.wait().await.unwrap();
+        assert_matches!(
+            exit_status,
+            Some(openssh_proxy_client::ExitStatus::Exited(0))
+        );
+
+        proxy_client.close().await.unwrap();
+    }
+    #[cfg(feature = "proxy-client")]
+    run_test!(
+        test_unordered_proxy_agent_forwarding,
+        test_proxy_agent_forwarding_impl
+    );
+
+    #[cfg(feature = "proxy-client")]
+    async fn test_proxy_direct_tcpip_impl(conn0: Connection, conn1: Connection) {
+        use std::num::NonZeroUsize;
+
+        eprintln!("Creating remote process");
+        let cmd = "socat -u OPEN:/data TCP-LISTEN:1236,bind=127.0.0.1 >/dev/stderr";
+        let (established_session, stdios) = create_remote_process(conn0, cmd).await;
+
+        sleep(Duration::from_secs(1)).await;
+
+        let proxy_client = conn1
+            .into_proxy(NonZeroUsize::new(16).unwrap())
+            .await
+            .unwrap();
+
+        eprintln!("Opening direct-tcpip channel");
+        {
+            let stream = proxy_client
+                .open_direct_tcpip("127.0.0.1".into(), 1236, ([127, 0, 0, 1], 0).into())
+                .await
+                .unwrap();
+            tokio::pin!(stream);
+
+            eprintln!("Reading");
+
+            const DATA: &[u8] = "0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n".as_bytes();
+            let mut buffer = Vec::new();
+            stream.read_to_end(&mut buffer).await.unwrap();
+
+            assert_eq!(DATA, &*buffer);
+        }
+
+        proxy_client.close().await.unwrap();
+
+        drop(stdios);
+
+        eprintln!("Waiting for session to end");
+        let session_status = established_session.wait().await.unwrap();
+        assert_matches!(
+            session_status,
+            SessionStatus::Exited { exit_value, .. }
+                if exit_value.unwrap() == 0
+        );
+    }
+    #[cfg(feature = "proxy-client")]
+    run_test2!(
+        test_unordered_proxy_direct_tcpip,
+        test_proxy_direct_tcpip_impl
+    );
+
+    #[cfg(feature = "proxy-client")]
+    async fn test_proxy_direct_streamlocal_impl(conn0: Connection, conn1: Connection) {
+        use std::num::NonZeroUsize;
+
+        let path = Path::new("/tmp/openssh-proxy-streamlocal.socket");
+
+        eprintln!("Creating remote process");
+        let cmd = format!("socat -u OPEN:/data UNIX-LISTEN:{:#?} >/dev/stderr", path);
+        let (established_session, stdios) = create_remote_process(conn0, &cmd).await;
+
+        sleep(Duration::from_secs(1)).await;
+
+        let proxy_client = conn1
+            .into_proxy(NonZeroUsize::new(16).unwrap())
+            .await
+            .unwrap();
+
+        eprintln!("Opening direct-streamlocal channel to non-existent socket");
+        assert_matches!(
+            proxy_client
+                .open_direct_streamlocal(Path::new("/tmp/openssh-proxy-non-existent.socket"))
+                .await,
+            Err(openssh_proxy_client::Error::ConnectFailed(_))
+        );
+
+        eprintln!("Opening direct-streamlocal channel");
+        {
+            let stream = proxy_client.open_direct_streamlocal(path).await.unwrap();
+            tokio::pin!(stream);
+
+            eprintln!("Reading");
+
+            const DATA: &[u8] = "0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n".as_bytes();
+            let mut buffer = Vec::new();
+            stream.read_to_end(&mut buffer).await.unwrap();
+
+            assert_eq!(DATA, &*buffer);
+        }
+
+        proxy_client.close().await.unwrap();
+
+        drop(stdios);
+
+        eprintln!("Waiting for session to end");
+        let session_status = established_session.wait().await.unwrap();
+        assert_matches!(
+            session_status,
+            SessionStatus::Exited { exit_value, .. }
+                if exit_value.unwrap() == 0
+        );
+    }
+    #[cfg(feature = "proxy-client")]
+    run_test2!(
+        test_unordered_proxy_direct_streamlocal,
+        test_proxy_direct_streamlocal_impl
+    );
+
+    #[cfg(feature = "proxy-client")]
+    async fn test_proxy_remote_forward_impl(conn0: Connection, conn1: Connection) {
+        use std::num::NonZeroUsize;
+
+        let proxy_client = conn0
+            .into_proxy(NonZeroUsize::new(16).unwrap())
+            .await
+            .unwrap();
+
+        eprintln!("Requesting remote forwarding");
+        let mut listener = proxy_client
+            .request_remote_forward(Cow::Borrowed("127.0.0.1"), 0)
+            .await
+            .unwrap();
+        let port = listener.port();
+        assert_ne!(port, 0);
+
+        eprintln!("Creating remote process");
+        let cmd = format!("socat -u OPEN:/data TCP:127.0.0.1:{} >/dev/stderr", port);
+        let (established_session, stdios) = create_remote_process(conn1, &cmd).await;
+
+        eprintln!("Accepting forwarded-tcpip channel");
+        {
+            let forwarded = listener.accept().await.unwrap();
+            assert_eq!(forwarded.originator_address, "127.0.0.1");
+
+            let stream = forwarded.stream;
+            tokio::pin!(stream);
+
+            eprintln!("Reading");
+
+            const DATA: &[u8] = "0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n".as_bytes();
+            let mut buffer = Vec::new();
+            stream.read_to_end(&mut buffer).await.unwrap();
+
+            assert_eq!(DATA, &*buffer);
+        }
+
+        drop(listener);
+        proxy_client.close().await.unwrap();
+
+        drop(stdios);
+
+        eprintln!("Waiting for session to end");
+        let session_status = established_session.wait().await.unwrap();
+        assert_matches!(
+            session_status,
+            SessionStatus::Exited { exit_value, .. }
+                if exit_value.unwrap() == 0
+        );
+    }
+    #[cfg(feature = "proxy-client")]
+    run_test2!(
+        test_unordered_proxy_remote_forward,
+        test_proxy_remote_forward_impl
+    );
+
+    #[cfg(feature = "proxy-client")]
+    async fn test_proxy_remote_streamlocal_forward_impl(conn0: Connection, conn1: Connection) {
+        use std::num::NonZeroUsize;
+
+        let path = Path::new("/tmp/openssh-proxy-remote-streamlocal.socket");
+
+        let proxy_client = conn0
+            .into_proxy(NonZeroUsize::new(16).unwrap())
+            .await
+            .unwrap();
+
+        eprintln!("Requesting remote streamlocal forwarding");
+        let mut listener = proxy_client
+            .request_remote_streamlocal_forward(path)
+            .await
+            .unwrap();
+        assert_eq!(listener.socket_path(), path);
+
+        eprintln!("Creating remote process");
+        let cmd = format!("socat -u OPEN:/data UNIX-CONNECT:{:#?} >/dev/stderr", path);
+        let (established_session, stdios) = create_remote_process(conn1, &cmd).await;
+
+        eprintln!("Accepting forwarded-streamlocal@openssh.com channel");
+        {
+            let forwarded = listener.accept().await.unwrap();
+            assert_eq!(forwarded.socket_path, path);
+
+            let stream = forwarded.stream;
+            tokio::pin!(stream);
+
+            eprintln!("Reading");
+
+            const DATA: &[u8] = "0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n".as_bytes();
+            let mut buffer = Vec::new();
+            stream.read_to_end(&mut buffer).await.unwrap();
+
+            assert_eq!(DATA, &*buffer);
+        }
+
+        drop(listener);
+        proxy_client.close().await.unwrap();
+
+        drop(stdios);
+
+        eprintln!("Waiting for session to end");
+        let session_status = established_session.wait().await.unwrap();
+        assert_matches!(
+            session_status,
+            SessionStatus::Exited { exit_value, .. }
+                if exit_value.unwrap() == 0
+        );
+    }
+    #[cfg(feature = "proxy-client")]
+    run_test2!(
+        test_unordered_proxy_remote_streamlocal_forward,
+        test_proxy_remote_streamlocal_forward_impl
+    );
 }
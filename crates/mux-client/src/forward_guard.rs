@@ -0,0 +1,187 @@
+#![forbid(unsafe_code)]
+
+use super::{request::Fwd, Connection, Error, ForwardType, Result, Socket};
+
+use std::num::NonZeroU32;
+
+#[derive(Debug)]
+enum Forward {
+    Port {
+        forward_type: ForwardType,
+        connect_socket: Socket<'static>,
+    },
+    Dynamic,
+}
+
+/// Port forwarding that is closed once dropped or explicitly closed
+/// via [`ForwardGuard::close`].
+///
+/// It owns a dedicated [`Connection`] to the ssh mux server, which is used
+/// to close the forwarding.
+///
+/// The ssh mux server cannot list the forwardings it has, so register
+/// the guards in a [`ForwardRegistry`] to list the active forwardings.
+///
+/// # Cancel safety
+///
+/// All methods of this struct is not cancellation safe.
+#[derive(Debug)]
+pub struct ForwardGuard {
+    /// Only `None` after the forwarding is closed.
+    conn: Option<Connection>,
+
+    forward: Forward,
+    listen_socket: Socket<'static>,
+    allocated_port: Option<NonZeroU32>,
+}
+
+impl ForwardGuard {
+    /// Request for local/remote port forwarding using `conn`.
+    ///
+    /// Check [`Connection::request_port_forward`] for more information.
+    pub async fn request_port_forward(
+        mut conn: Connection,
+        forward_type: ForwardType,
+        listen_socket: Socket<'static>,
+        connect_socket: Socket<'static>,
+    ) -> Result<Self> {
+        let allocated_port = conn
+            .request_port_forward(forward_type, &listen_socket, &connect_socket)
+            .await?;
+
+        Ok(Self {
+            conn: Some(conn),
+            forward: Forward::Port {
+                forward_type,
+                connect_socket,
+            },
+            listen_socket,
+            allocated_port,
+        })
+    }
+
+    /// Request for dynamic forwarding using `conn`.
+    ///
+    /// Check [`Connection::request_dynamic_forward`] for more information.
+    pub async fn request_dynamic_forward(
+        mut conn: Connection,
+        listen_socket: Socket<'static>,
+    ) -> Result<Self> {
+        let allocated_port = conn.request_dynamic_forward(&listen_socket).await?;
+
+        Ok(Self {
+            conn: Some(conn),
+            forward: Forward::Dynamic,
+            listen_socket,
+            allocated_port: Some(allocated_port),
+        })
+    }
+
+    /// Return `None` for dynamic forwarding.
+    pub fn forward_type(&self) -> Option<ForwardType> {
+        match &self.forward {
+            Forward::Port { forward_type, .. } => Some(*forward_type),
+            Forward::Dynamic => None,
+        }
+    }
+
+    pub fn listen_socket(&self) -> &Socket<'static> {
+        &self.listen_socket
+    }
+
+    /// Return `None` for dynamic forwarding.
+    pub fn connect_socket(&self) -> Option<&Socket<'static>> {
+        match &self.forward {
+            Forward::Port { connect_socket, .. } => Some(connect_socket),
+            Forward::Dynamic => None,
+        }
+    }
+
+    /// Port allocated by the ssh mux server, if any.
+    pub fn allocated_port(&self) -> Option<NonZeroU32> {
+        self.allocated_port
+    }
+
+    fn as_fwd(&self) -> Fwd<'_> {
+        let listen_socket = &self.listen_socket;
+
+        match &self.forward {
+            Forward::Port {
+                forward_type: ForwardType::Local,
+                connect_socket,
+            } => Fwd::Local {
+                listen_socket,
+                connect_socket,
+            },
+            Forward::Port {
+                forward_type: ForwardType::Remote,
+                connect_socket,
+            } => Fwd::Remote {
+                listen_socket,
+                connect_socket,
+            },
+            Forward::Dynamic => Fwd::Dynamic { listen_socket },
+        }
+    }
+
+    /// Close the forwarding and return the underlying connection.
+    ///
+    /// On error, the guard is returned along with the error, so that
+    /// the forwarding is not leaked.
+    pub async fn close(mut self) -> Result<Connection, (Error, Self)> {
+        let mut conn = self.conn.take().unwrap();
+
+        match conn.close_fwd(&self.as_fwd()).await {
+            Ok(()) => Ok(conn),
+            Err(err) => {
+                self.conn = Some(conn);
+                Err((err, self))
+            }
+        }
+    }
+}
+
+impl Drop for ForwardGuard {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            // There is nothing we can do about the error here.
+            let _ = conn.close_fwd_sync(&self.as_fwd());
+        }
+    }
+}
+
+/// Registry of the active forwardings.
+///
+/// All forwardings still registered are closed once it is dropped.
+#[derive(Debug, Default)]
+pub struct ForwardRegistry(Vec<ForwardGuard>);
+
+impl ForwardRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `guard` and return its index in [`ForwardRegistry::list`].
+    pub fn register(&mut self, guard: ForwardGuard) -> usize {
+        self.0.push(guard);
+        self.0.len() - 1
+    }
+
+    /// List the active forwardings in the order they are registered.
+    pub fn list(&self) -> &[ForwardGuard] {
+        &self.0
+    }
+
+    /// Unregister the forwarding at `index` in [`ForwardRegistry::list`],
+    /// shifting all forwardings after it to the left.
+    ///
+    /// The forwarding is closed once the returned guard is dropped or
+    /// closed via [`ForwardGuard::close`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn unregister(&mut self, index: usize) -> ForwardGuard {
+        self.0.remove(index)
+    }
+}
@@ -17,6 +17,9 @@ impl ErrorExt for Error {
     }
 }
 
+#[cfg(feature = "proxy-client")]
+pub use openssh_proxy_client as proxy_client;
+
 pub mod default_config;
 
 mod connection;
@@ -33,11 +36,23 @@ pub use response::Response;
 mod session;
 pub use session::*;
 
+mod forward_guard;
+pub use forward_guard::{ForwardGuard, ForwardRegistry};
+
+mod stdio_forward;
+pub use stdio_forward::*;
+
 mod shutdown_mux_master;
-pub use shutdown_mux_master::shutdown_mux_master;
+pub use shutdown_mux_master::{shutdown_mux_master, terminate_mux_master};
 
 mod utils;
 
+#[cfg(any(test, feature = "mux-server"))]
+pub mod mux_server;
+
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+
 #[cfg(test)]
 #[macro_use]
 extern crate assert_matches;
@@ -0,0 +1,843 @@
+//! Server side of the ssh mux protocol.
+//!
+//! [`MuxServer`] accepts mux clients (e.g. `ssh -S`) on a control socket
+//! and dispatches their requests to a user-supplied [`MuxBackend`], so that
+//! an ssh connection owned by the current process can be shared with
+//! stock openssh clients.
+
+use crate::{constants, ForwardType, NonZeroByteVec, Response, Session, Socket};
+
+use std::{
+    borrow::Cow,
+    convert::TryInto,
+    ffi::OsString,
+    fs,
+    future::Future,
+    io::{self, IoSlice},
+    num::NonZeroU32,
+    os::unix::{
+        ffi::OsStringExt,
+        io::{FromRawFd, OwnedFd, RawFd},
+    },
+    path::{Path, PathBuf},
+    pin::Pin,
+    process,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use sendfd::RecvWithFd;
+use serde::{Deserialize, Serialize};
+use ssh_format::{from_bytes, Serializer};
+use tokio::{
+    io::AsyncReadExt,
+    net::{UnixListener, UnixStream},
+    select, spawn,
+    sync::Notify,
+};
+use tokio_io_utility::write_vectored_all;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Request received from a mux client.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum MuxRequest {
+    Hello {
+        version: u32,
+    },
+
+    AliveCheck {
+        request_id: u32,
+    },
+
+    Terminate {
+        request_id: u32,
+    },
+
+    NewSession {
+        request_id: u32,
+        session: Session<'static>,
+        /// stdin, stdout and stderr sent by the client.
+        fds: Vec<OwnedFd>,
+    },
+
+    OpenFwd {
+        request_id: u32,
+        /// `None` for dynamic forwarding.
+        forward_type: Option<ForwardType>,
+        listen_socket: Socket<'static>,
+        connect_socket: Socket<'static>,
+    },
+
+    CloseFwd {
+        request_id: u32,
+        /// `None` for dynamic forwarding.
+        forward_type: Option<ForwardType>,
+        listen_socket: Socket<'static>,
+        connect_socket: Socket<'static>,
+    },
+
+    NewStdioFwd {
+        request_id: u32,
+        connect_socket: Socket<'static>,
+        /// stdin and stdout sent by the client.
+        fds: Vec<OwnedFd>,
+    },
+
+    StopListening {
+        request_id: u32,
+    },
+}
+
+impl MuxRequest {
+    /// Id of the request, which must be sent back in the `response_id`
+    /// of its responses, e.g. to reply to any request with the same
+    /// [`Response`].
+    ///
+    /// Return `None` for [`MuxRequest::Hello`].
+    pub fn request_id(&self) -> Option<u32> {
+        use MuxRequest::*;
+
+        match self {
+            Hello { .. } => None,
+            AliveCheck { request_id }
+            | Terminate { request_id }
+            | NewSession { request_id, .. }
+            | OpenFwd { request_id, .. }
+            | CloseFwd { request_id, .. }
+            | NewStdioFwd { request_id, .. }
+            | StopListening { request_id } => Some(*request_id),
+        }
+    }
+}
+
+/// Reason for refusing a request, sent back to the mux client.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Rejection {
+    /// Sent as `MUX_S_PERMISSION_DENIED`.
+    PermissionDenied(Box<str>),
+
+    /// Sent as `MUX_S_FAILURE`.
+    Failure(Box<str>),
+}
+
+impl Rejection {
+    fn unsupported(request: &str) -> Self {
+        Rejection::Failure(format!("{} is not supported", request).into_boxed_str())
+    }
+
+    fn into_response(self, response_id: u32) -> Response {
+        match self {
+            Rejection::PermissionDenied(reason) => Response::PermissionDenied {
+                response_id,
+                reason,
+            },
+            Rejection::Failure(reason) => Response::Failure {
+                response_id,
+                reason,
+            },
+        }
+    }
+}
+
+/// Session opened by [`MuxBackend::new_session`].
+pub struct OpenedSession {
+    /// Set to `true` if the remote failed to allocate a tty, so that
+    /// the client can return its local tty to cooked mode.
+    pub tty_alloc_fail: bool,
+
+    /// Resolved once the remote process exits.
+    ///
+    /// If it resolves to `None`, the connection is closed without
+    /// sending the exit value.
+    pub exit_value: BoxFuture<'static, Option<u32>>,
+}
+
+/// Handler of requests received by [`MuxServer`].
+///
+/// Every method except [`MuxBackend::new_session`] has a default
+/// implementation, which rejects the request unless documented otherwise.
+pub trait MuxBackend: Send + Sync + 'static {
+    /// Return pid sent in reply to alive check.
+    ///
+    /// Default to pid of the current process.
+    fn server_pid(&self) -> u32 {
+        process::id()
+    }
+
+    /// Open a new session, `fds` are the stdin, stdout and stderr
+    /// sent by the client.
+    fn new_session(
+        &self,
+        session: Session<'static>,
+        fds: [OwnedFd; 3],
+    ) -> BoxFuture<'_, Result<OpenedSession, Rejection>>;
+
+    /// Forward `fds` (stdin and stdout of the client) to `connect_socket`
+    /// on remote.
+    ///
+    /// On success, return a future which resolves once the forwarding
+    /// is torn down.
+    fn new_stdio_forward(
+        &self,
+        connect_socket: Socket<'static>,
+        fds: [OwnedFd; 2],
+    ) -> BoxFuture<'_, Result<BoxFuture<'static, ()>, Rejection>> {
+        let _ = (connect_socket, fds);
+        Box::pin(async { Err(Rejection::unsupported("Stdio forwarding")) })
+    }
+
+    /// Open forwarding, `forward_type` is `None` for dynamic forwarding.
+    ///
+    /// For remote forwarding with dynamically allocated port, return
+    /// the port allocated.
+    fn open_forward(
+        &self,
+        forward_type: Option<ForwardType>,
+        listen_socket: Socket<'static>,
+        connect_socket: Socket<'static>,
+    ) -> BoxFuture<'_, Result<Option<NonZeroU32>, Rejection>> {
+        let _ = (forward_type, listen_socket, connect_socket);
+        Box::pin(async { Err(Rejection::unsupported("Port forwarding")) })
+    }
+
+    /// Close forwarding opened by [`MuxBackend::open_forward`].
+    fn close_forward(
+        &self,
+        forward_type: Option<ForwardType>,
+        listen_socket: Socket<'static>,
+        connect_socket: Socket<'static>,
+    ) -> BoxFuture<'_, Result<(), Rejection>> {
+        let _ = (forward_type, listen_socket, connect_socket);
+        Box::pin(async { Err(Rejection::unsupported("Port forwarding")) })
+    }
+
+    /// Called before [`MuxServer`] stops accepting new clients and
+    /// removes its socket.
+    ///
+    /// Default to accept the request.
+    fn stop_listening(&self) -> BoxFuture<'_, Result<(), Rejection>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Terminate the ssh connection, [`MuxServer`] also stops listening
+    /// on success.
+    fn terminate(&self) -> BoxFuture<'_, Result<(), Rejection>> {
+        Box::pin(async {
+            Err(Rejection::PermissionDenied(
+                "Terminate is not supported".into(),
+            ))
+        })
+    }
+}
+
+/// Mux server listening on a unix socket.
+///
+/// The socket is removed once it is dropped.
+#[derive(Debug)]
+pub struct MuxServer<B> {
+    path: PathBuf,
+    listener: UnixListener,
+    backend: Arc<B>,
+}
+
+impl<B: MuxBackend> MuxServer<B> {
+    pub fn bind<P: AsRef<Path>>(path: P, backend: B) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&path)?;
+
+        Ok(Self {
+            path,
+            listener,
+            backend: Arc::new(backend),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Accept mux clients and serve each of them in a newly spawned
+    /// tokio task.
+    ///
+    /// Return once a client successfully requests stop listening or
+    /// terminate, clients already accepted continue to be served.
+    pub async fn run(self) -> io::Result<()> {
+        let stop = Arc::new(Notify::new());
+        let session_id = Arc::new(AtomicU32::new(0));
+
+        loop {
+            let (stream, _addr) = select! {
+                res = self.listener.accept() => res?,
+                _ = stop.notified() => break Ok(()),
+            };
+
+            let backend = self.backend.clone();
+            let stop = stop.clone();
+            let session_id = session_id.clone();
+
+            spawn(async move {
+                // The connection is simply closed on any error.
+                let _ = ServerConnection::new(stream)
+                    .serve(&*backend, &stop, &session_id)
+                    .await;
+            });
+        }
+    }
+}
+
+impl<B> Drop for MuxServer<B> {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Same as `CHANNEL_MUX_MAX_PACKET` in openssh.
+const MAX_PACKET_SIZE: usize = 256 * 1024;
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn parse<'de, T: Deserialize<'de>>(bytes: &mut &'de [u8]) -> io::Result<T> {
+    let (value, rest) =
+        from_bytes(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    *bytes = rest;
+    Ok(value)
+}
+
+fn parse_non_zero_byte_vec(bytes: &mut &[u8]) -> io::Result<NonZeroByteVec> {
+    NonZeroByteVec::new(parse(bytes)?).ok_or_else(|| invalid_data("Unexpected null byte"))
+}
+
+fn parse_socket(bytes: &mut &[u8]) -> io::Result<Socket<'static>> {
+    let (addr, port): (Vec<u8>, u32) = parse(bytes)?;
+
+    let unix_socket_port: i32 = -2;
+
+    Ok(if port == unix_socket_port as u32 {
+        Socket::UnixSocket {
+            path: PathBuf::from(OsString::from_vec(addr)).into(),
+        }
+    } else {
+        Socket::TcpSocket {
+            port,
+            host: String::from_utf8(addr)
+                .map_err(|_| invalid_data("Host is not utf-8"))?
+                .into(),
+        }
+    })
+}
+
+fn parse_fwd(
+    bytes: &mut &[u8],
+) -> io::Result<(Option<ForwardType>, Socket<'static>, Socket<'static>)> {
+    let forward_type = match parse(bytes)? {
+        constants::MUX_FWD_LOCAL => Some(ForwardType::Local),
+        constants::MUX_FWD_REMOTE => Some(ForwardType::Remote),
+        constants::MUX_FWD_DYNAMIC => None,
+        _ => return Err(invalid_data("Unknown forward type")),
+    };
+
+    Ok((forward_type, parse_socket(bytes)?, parse_socket(bytes)?))
+}
+
+pub(crate) struct ServerConnection {
+    raw_conn: UnixStream,
+    serializer: Serializer,
+    read_buffer: Vec<u8>,
+}
+
+impl ServerConnection {
+    pub(crate) fn new(raw_conn: UnixStream) -> Self {
+        Self {
+            raw_conn,
+            serializer: Serializer::new(Vec::new()),
+            read_buffer: Vec::new(),
+        }
+    }
+
+    async fn serve<B: MuxBackend>(
+        mut self,
+        backend: &B,
+        stop: &Notify,
+        session_id: &AtomicU32,
+    ) -> io::Result<()> {
+        use MuxRequest::*;
+
+        self.write(&Response::Hello {
+            version: constants::SSHMUX_VER,
+        })
+        .await?;
+
+        match self.read_request().await? {
+            Hello { version } if version == constants::SSHMUX_VER => (),
+            Hello { .. } => return Err(invalid_data("Unsupported mux protocol")),
+            _ => return Err(invalid_data("Expected hello message")),
+        }
+
+        loop {
+            let request = match self.read_request().await {
+                Ok(request) => request,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break Ok(()),
+                Err(err) => break Err(err),
+            };
+
+            let response = match request {
+                Hello { .. } => break Err(invalid_data("Unexpected hello message")),
+                AliveCheck { request_id } => Response::Alive {
+                    response_id: request_id,
+                    server_pid: backend.server_pid(),
+                },
+                Terminate { request_id } => match backend.terminate().await {
+                    Ok(()) => {
+                        stop.notify_one();
+                        Response::Ok {
+                            response_id: request_id,
+                        }
+                    }
+                    Err(rejection) => rejection.into_response(request_id),
+                },
+                StopListening { request_id } => match backend.stop_listening().await {
+                    Ok(()) => {
+                        stop.notify_one();
+                        Response::Ok {
+                            response_id: request_id,
+                        }
+                    }
+                    Err(rejection) => rejection.into_response(request_id),
+                },
+                OpenFwd {
+                    request_id,
+                    forward_type,
+                    listen_socket,
+                    connect_socket,
+                } => match backend
+                    .open_forward(forward_type, listen_socket, connect_socket)
+                    .await
+                {
+                    Ok(Some(remote_port)) => Response::RemotePort {
+                        response_id: request_id,
+                        remote_port: remote_port.get(),
+                    },
+                    Ok(None) => Response::Ok {
+                        response_id: request_id,
+                    },
+                    Err(rejection) => rejection.into_response(request_id),
+                },
+                CloseFwd {
+                    request_id,
+                    forward_type,
+                    listen_socket,
+                    connect_socket,
+                } => match backend
+                    .close_forward(forward_type, listen_socket, connect_socket)
+                    .await
+                {
+                    Ok(()) => Response::Ok {
+                        response_id: request_id,
+                    },
+                    Err(rejection) => rejection.into_response(request_id),
+                },
+                NewSession {
+                    request_id,
+                    session,
+                    fds,
+                } => {
+                    let fds = fds.try_into().map_err(|_| invalid_data("Expected 3 fds"))?;
+
+                    match backend.new_session(session, fds).await {
+                        Ok(opened_session) => {
+                            return self
+                                .serve_session(request_id, session_id, opened_session)
+                                .await
+                        }
+                        Err(rejection) => rejection.into_response(request_id),
+                    }
+                }
+                NewStdioFwd {
+                    request_id,
+                    connect_socket,
+                    fds,
+                } => {
+                    let fds = fds.try_into().map_err(|_| invalid_data("Expected 2 fds"))?;
+
+                    match backend.new_stdio_forward(connect_socket, fds).await {
+                        Ok(forwarding) => {
+                            self.write(&Response::SessionOpened {
+                                response_id: request_id,
+                                session_id: session_id.fetch_add(1, Ordering::Relaxed),
+                            })
+                            .await?;
+
+                            // The connection is closed once the forwarding
+                            // is torn down.
+                            forwarding.await;
+                            return Ok(());
+                        }
+                        Err(rejection) => rejection.into_response(request_id),
+                    }
+                }
+            };
+
+            self.write(&response).await?;
+        }
+    }
+
+    async fn serve_session(
+        &mut self,
+        response_id: u32,
+        session_id: &AtomicU32,
+        opened_session: OpenedSession,
+    ) -> io::Result<()> {
+        let session_id = session_id.fetch_add(1, Ordering::Relaxed);
+
+        self.write(&Response::SessionOpened {
+            response_id,
+            session_id,
+        })
+        .await?;
+
+        if opened_session.tty_alloc_fail {
+            self.write(&Response::TtyAllocFail { session_id }).await?;
+        }
+
+        if let Some(exit_value) = opened_session.exit_value.await {
+            self.write(&Response::ExitMessage {
+                session_id,
+                exit_value,
+            })
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn write(&mut self, response: &Response) -> io::Result<()> {
+        let serializer = &mut self.serializer;
+
+        serializer.reset_counter();
+        serializer.output.clear();
+
+        let to_io_err = |err| io::Error::new(io::ErrorKind::InvalidInput, err);
+
+        response.serialize(&mut *serializer).map_err(to_io_err)?;
+        let header = serializer.create_header(0).map_err(to_io_err)?;
+
+        write_vectored_all(
+            &mut self.raw_conn,
+            &mut [IoSlice::new(&header), IoSlice::new(&serializer.output)],
+        )
+        .await
+    }
+
+    /// Receive `n` fds, each sent with one byte.
+    async fn recv_fds(&self, n: usize) -> io::Result<Vec<OwnedFd>> {
+        let mut fds = Vec::with_capacity(n);
+
+        while fds.len() < n {
+            self.raw_conn.readable().await?;
+
+            let mut byte = [0];
+            let mut fd: [RawFd; 1] = [-1];
+
+            match self.raw_conn.recv_with_fd(&mut byte, &mut fd) {
+                Ok((0, _)) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok((_, 1)) => {
+                    // Safety: fd is just received and is not owned by anyone else.
+                    fds.push(unsafe { OwnedFd::from_raw_fd(fd[0]) })
+                }
+                Ok(_) => return Err(invalid_data("Expected fd")),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(fds)
+    }
+
+    pub(crate) async fn read_request(&mut self) -> io::Result<MuxRequest> {
+        use constants::*;
+
+        // Read exactly one packet, so that bytes sent along with fds
+        // are not consumed here.
+        let mut header = [0_u8; 4];
+        self.raw_conn.read_exact(&mut header).await?;
+        let packet_len = u32::from_be_bytes(header) as usize;
+        if packet_len > MAX_PACKET_SIZE {
+            return Err(invalid_data("Packet too large"));
+        }
+
+        let buffer = &mut self.read_buffer;
+        buffer.resize(packet_len, 0);
+        self.raw_conn.read_exact(buffer).await?;
+
+        let mut bytes = &buffer[..];
+        let bytes = &mut bytes;
+
+        let packet_type: u32 = parse(bytes)?;
+
+        if packet_type == MUX_MSG_HELLO {
+            // Ignore extensions
+            return Ok(MuxRequest::Hello {
+                version: parse(bytes)?,
+            });
+        }
+
+        let request_id: u32 = parse(bytes)?;
+
+        Ok(match packet_type {
+            MUX_C_ALIVE_CHECK => MuxRequest::AliveCheck { request_id },
+            MUX_C_TERMINATE => MuxRequest::Terminate { request_id },
+            MUX_C_STOP_LISTENING => MuxRequest::StopListening { request_id },
+            MUX_C_NEW_SESSION => {
+                let (_reserved, tty, x11_forwarding, agent, subsystem, escape_ch): (
+                    &[u8],
+                    bool,
+                    bool,
+                    bool,
+                    bool,
+                    char,
+                ) = parse(bytes)?;
+
+                let term = parse_non_zero_byte_vec(bytes)?;
+                let cmd = parse_non_zero_byte_vec(bytes)?;
+
+                let mut env = Vec::new();
+                while !bytes.is_empty() {
+                    env.push(Cow::Owned(parse_non_zero_byte_vec(bytes)?));
+                }
+
+                let session = Session {
+                    tty,
+                    x11_forwarding,
+                    agent,
+                    subsystem,
+                    escape_ch,
+                    term: Cow::Owned(term),
+                    cmd: Cow::Owned(cmd),
+                    env: Cow::Owned(env),
+                };
+
+                MuxRequest::NewSession {
+                    request_id,
+                    session,
+                    fds: self.recv_fds(3).await?,
+                }
+            }
+            MUX_C_OPEN_FWD => {
+                let (forward_type, listen_socket, connect_socket) = parse_fwd(bytes)?;
+
+                MuxRequest::OpenFwd {
+                    request_id,
+                    forward_type,
+                    listen_socket,
+                    connect_socket,
+                }
+            }
+            MUX_C_CLOSE_FWD => {
+                let (forward_type, listen_socket, connect_socket) = parse_fwd(bytes)?;
+
+                MuxRequest::CloseFwd {
+                    request_id,
+                    forward_type,
+                    listen_socket,
+                    connect_socket,
+                }
+            }
+            MUX_C_NEW_STDIO_FWD => {
+                let _reserved: &[u8] = parse(bytes)?;
+                let connect_socket = parse_socket(bytes)?;
+
+                MuxRequest::NewStdioFwd {
+                    request_id,
+                    connect_socket,
+                    fds: self.recv_fds(2).await?,
+                }
+            }
+            _ => return Err(invalid_data("Unsupported request")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Connection, Error, SessionStatus};
+
+    use std::{env, fs::File, io::Write, os::unix::io::AsRawFd};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_pipe::pipe;
+
+    fn socket_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!(
+            "openssh-mux-client-server-{}-{}.socket",
+            process::id(),
+            name
+        ))
+    }
+
+    struct TestBackend;
+
+    impl MuxBackend for TestBackend {
+        fn new_session(
+            &self,
+            session: Session<'static>,
+            fds: [OwnedFd; 3],
+        ) -> BoxFuture<'_, Result<OpenedSession, Rejection>> {
+            Box::pin(async move {
+                if session.cmd.into_inner() != b"echo" {
+                    return Err(Rejection::PermissionDenied("only echo is allowed".into()));
+                }
+
+                let [_stdin, stdout, _stderr] = fds;
+                File::from(stdout).write_all(b"hello").unwrap();
+
+                Ok(OpenedSession {
+                    tty_alloc_fail: session.tty,
+                    exit_value: Box::pin(async { Some(5) }),
+                })
+            })
+        }
+
+        fn open_forward(
+            &self,
+            forward_type: Option<ForwardType>,
+            _listen_socket: Socket<'static>,
+            _connect_socket: Socket<'static>,
+        ) -> BoxFuture<'_, Result<Option<NonZeroU32>, Rejection>> {
+            Box::pin(async move {
+                match forward_type {
+                    Some(ForwardType::Remote) => Ok(NonZeroU32::new(2345)),
+                    Some(ForwardType::Local) => Ok(None),
+                    None => Err(Rejection::Failure("no dynamic forwarding".into())),
+                }
+            })
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_server_alive_check_and_stop_listening() {
+        let server = MuxServer::bind(socket_path("stop"), TestBackend).unwrap();
+        let path = server.path().to_path_buf();
+        let task = spawn(server.run());
+
+        let mut conn = Connection::connect(&path).await.unwrap();
+        assert_eq!(conn.send_alive_check().await.unwrap().get(), process::id());
+
+        conn.request_stop_listening().await.unwrap();
+        task.await.unwrap().unwrap();
+        assert!(!path.exists());
+
+        // Accepted clients continue to be served
+        assert_eq!(conn.send_alive_check().await.unwrap().get(), process::id());
+        assert_matches!(
+            conn.request_terminate().await,
+            Err(Error::PermissionDenied(reason)) if &*reason == "Terminate is not supported"
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_server_new_session() {
+        let server = MuxServer::bind(socket_path("session"), TestBackend).unwrap();
+        let path = server.path().to_path_buf();
+        let _task = spawn(server.run());
+
+        let (mut stdout_read, stdout_write) = pipe().unwrap();
+        let fds = [
+            io::stdin().as_raw_fd(),
+            stdout_write.as_raw_fd(),
+            io::stderr().as_raw_fd(),
+        ];
+
+        let conn = Connection::connect(&path).await.unwrap();
+        let session = Session::builder()
+            .cmd(Cow::Borrowed("cat".try_into().unwrap()))
+            .build();
+        assert_matches!(
+            conn.open_new_session(&session, &fds).await,
+            Err(Error::PermissionDenied(reason)) if &*reason == "only echo is allowed"
+        );
+
+        let conn = Connection::connect(&path).await.unwrap();
+        let session = Session::builder()
+            .tty(true)
+            .cmd(Cow::Borrowed("echo".try_into().unwrap()))
+            .build();
+        let established_session = conn.open_new_session(&session, &fds).await.unwrap();
+        drop(stdout_write);
+
+        let established_session = match established_session.wait().await.unwrap() {
+            SessionStatus::TtyAllocFail(established_session) => established_session,
+            session_status => panic!("Unexpected {:#?}", session_status),
+        };
+        assert_matches!(
+            established_session.wait().await.unwrap(),
+            SessionStatus::Exited {
+                exit_value: Some(5)
+            }
+        );
+
+        let mut output = Vec::new();
+        stdout_read.read_to_end(&mut output).await.unwrap();
+        assert_eq!(output, b"hello");
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_server_reject_oversized_packet() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let mut conn = ServerConnection::new(server);
+
+        client
+            .write_all(&(MAX_PACKET_SIZE as u32 + 1).to_be_bytes())
+            .await
+            .unwrap();
+
+        let err = conn.read_request().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(conn.read_buffer.capacity() <= MAX_PACKET_SIZE);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_server_port_forward() {
+        let server = MuxServer::bind(socket_path("forward"), TestBackend).unwrap();
+        let path = server.path().to_path_buf();
+        let _task = spawn(server.run());
+
+        let mut conn = Connection::connect(&path).await.unwrap();
+
+        let listen_socket = Socket::TcpSocket {
+            port: 0,
+            host: "127.0.0.1".into(),
+        };
+        let connect_socket = Socket::UnixSocket {
+            path: Path::new("/tmp/socket").into(),
+        };
+
+        assert_eq!(
+            conn.request_port_forward(ForwardType::Remote, &listen_socket, &connect_socket)
+                .await
+                .unwrap(),
+            NonZeroU32::new(2345)
+        );
+        assert_eq!(
+            conn.request_port_forward(ForwardType::Local, &listen_socket, &connect_socket)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_matches!(
+            conn.request_dynamic_forward(&listen_socket).await,
+            Err(Error::RequestFailure(reason)) if &*reason == "no dynamic forwarding"
+        );
+        assert_matches!(
+            conn.close_port_forward(ForwardType::Local, &listen_socket, &connect_socket)
+                .await,
+            Err(Error::RequestFailure(reason)) if &*reason == "Port forwarding is not supported"
+        );
+    }
+}
@@ -16,6 +16,12 @@ pub(crate) enum Request {
     /// Server replied with `Response::Alive`.
     AliveCheck { request_id: u32 },
 
+    /// A client may request that a master terminate immediately.
+    ///
+    /// A server may reply with `Response::Ok`, `Response::PermissionDenied` or
+    /// `Response::Failure`, though it might also terminate before replying.
+    Terminate { request_id: u32 },
+
     /// For opening a new multiplexed session in passenger mode,
     /// send this variant and then sends stdin, stdout and stderr fd.
     ///
@@ -51,12 +57,32 @@ pub(crate) enum Request {
     /// or `Response::Failure`.
     CloseFwd { request_id: u32, fwd_mode: u32 },
 
+    /// For forwarding stdio of the client to `host:port` on remote
+    /// (`ssh -W`), send this variant and then sends stdin and stdout fd.
+    ///
+    /// If successful, the server will reply with `Response::SessionOpened`.
+    ///
+    /// Otherwise it will reply with an error:
+    ///  - `Response::PermissionDenied`;
+    ///  - `Response::Failure`.
+    ///
+    /// The server closes the connection once the forwarding is torn down.
+    NewStdioFwd { request_id: u32 },
+
     /// A client may request the master to stop accepting new multiplexing requests
     /// and remove its listener socket.
     ///
     /// A server may reply with `Response::Ok`, `Response::PermissionDenied` or
     /// `Response::Failure`.
     StopListening { request_id: u32 },
+
+    /// Request the master to turn this connection into a proxy that
+    /// speaks the ssh connection protocol.
+    ///
+    /// A server may reply with `Response::Proxy`, `Response::PermissionDenied`
+    /// or `Response::Failure`.
+    #[cfg(feature = "proxy-client")]
+    Proxy { request_id: u32 },
 }
 impl Serialize for Request {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
@@ -73,6 +99,12 @@ impl Serialize for Request {
                 "AliveCheck",
                 request_id,
             ),
+            Terminate { request_id } => serializer.serialize_newtype_variant(
+                "Request",
+                MUX_C_TERMINATE,
+                "Terminate",
+                request_id,
+            ),
             NewSession {
                 request_id,
                 session,
@@ -100,12 +132,22 @@ impl Serialize for Request {
                 "CloseFwd",
                 &(*request_id, fwd_mode),
             ),
+            NewStdioFwd { request_id } => serializer.serialize_newtype_variant(
+                "Request",
+                MUX_C_NEW_STDIO_FWD,
+                "NewStdioFwd",
+                &(*request_id, ""),
+            ),
             StopListening { request_id } => serializer.serialize_newtype_variant(
                 "Request",
                 MUX_C_STOP_LISTENING,
                 "StopListening",
                 request_id,
             ),
+            #[cfg(feature = "proxy-client")]
+            Proxy { request_id } => {
+                serializer.serialize_newtype_variant("Request", MUX_C_PROXY, "Proxy", request_id)
+            }
         }
     }
 }
@@ -147,6 +189,13 @@ pub struct Session<'a> {
     #[builder(default_code = r#"Cow::Borrowed(default_config::get_term())"#)]
     pub term: Cow<'a, NonZeroByteSlice>,
     pub cmd: Cow<'a, NonZeroByteSlice>,
+
+    /// Environment variables in the form of `NAME=value`.
+    ///
+    /// Note that sshd would ignore them unless `NAME` is listed in
+    /// `AcceptEnv` of its config.
+    #[builder(default = Cow::Borrowed(&[]))]
+    pub env: Cow<'a, [Cow<'a, NonZeroByteSlice>]>,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -205,6 +254,7 @@ trait PathExt {
 
     fn to_bytes(&self) -> Cow<'_, [u8]>;
 
+    #[cfg(not(unix))]
     fn to_string_lossy_and_as_bytes(&self) -> Cow<'_, [u8]>;
 }
 
@@ -228,6 +278,7 @@ impl PathExt for Path {
         }
     }
 
+    #[cfg(not(unix))]
     fn to_string_lossy_and_as_bytes(&self) -> Cow<'_, [u8]> {
         match self.to_string_lossy() {
             Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
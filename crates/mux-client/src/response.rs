@@ -4,6 +4,8 @@ use serde::{
     de::{Deserializer, EnumAccess, Error, VariantAccess, Visitor},
     Deserialize,
 };
+#[cfg(any(test, feature = "mux-server"))]
+use serde::{Serialize, Serializer};
 use std::{fmt, marker::PhantomData};
 
 use super::constants;
@@ -12,20 +14,49 @@ use super::constants;
 /// tuple and struct as the same.**
 #[derive(Clone, Debug)]
 pub enum Response {
-    Hello { version: u32 },
+    Hello {
+        version: u32,
+    },
 
-    Alive { response_id: u32, server_pid: u32 },
+    Alive {
+        response_id: u32,
+        server_pid: u32,
+    },
 
-    Ok { response_id: u32 },
-    Failure { response_id: u32, reason: Box<str> },
+    Ok {
+        response_id: u32,
+    },
+    Failure {
+        response_id: u32,
+        reason: Box<str>,
+    },
 
-    PermissionDenied { response_id: u32, reason: Box<str> },
+    PermissionDenied {
+        response_id: u32,
+        reason: Box<str>,
+    },
 
-    SessionOpened { response_id: u32, session_id: u32 },
-    ExitMessage { session_id: u32, exit_value: u32 },
-    TtyAllocFail { session_id: u32 },
+    SessionOpened {
+        response_id: u32,
+        session_id: u32,
+    },
+    ExitMessage {
+        session_id: u32,
+        exit_value: u32,
+    },
+    TtyAllocFail {
+        session_id: u32,
+    },
 
-    RemotePort { response_id: u32, remote_port: u32 },
+    RemotePort {
+        response_id: u32,
+        remote_port: u32,
+    },
+
+    #[cfg(feature = "proxy-client")]
+    Proxy {
+        response_id: u32,
+    },
 }
 impl<'de> Deserialize<'de> for Response {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
@@ -41,12 +72,96 @@ impl<'de> Deserialize<'de> for Response {
                 "ExitMessage",
                 "TtyAllocFail",
                 "RemotePort",
+                #[cfg(feature = "proxy-client")]
+                "Proxy",
             ],
             ResponseVisitor,
         )
     }
 }
 
+/// Used by the mux server side.
+#[cfg(any(test, feature = "mux-server"))]
+impl Serialize for Response {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use constants::*;
+        use Response::*;
+
+        match self {
+            Hello { version } => {
+                serializer.serialize_newtype_variant("Response", MUX_MSG_HELLO, "Hello", version)
+            }
+            Alive {
+                response_id,
+                server_pid,
+            } => serializer.serialize_newtype_variant(
+                "Response",
+                MUX_S_ALIVE,
+                "Alive",
+                &(*response_id, *server_pid),
+            ),
+            Ok { response_id } => {
+                serializer.serialize_newtype_variant("Response", MUX_S_OK, "Ok", response_id)
+            }
+            Failure {
+                response_id,
+                reason,
+            } => serializer.serialize_newtype_variant(
+                "Response",
+                MUX_S_FAILURE,
+                "Failure",
+                &(*response_id, reason),
+            ),
+            PermissionDenied {
+                response_id,
+                reason,
+            } => serializer.serialize_newtype_variant(
+                "Response",
+                MUX_S_PERMISSION_DENIED,
+                "PermissionDenied",
+                &(*response_id, reason),
+            ),
+            SessionOpened {
+                response_id,
+                session_id,
+            } => serializer.serialize_newtype_variant(
+                "Response",
+                MUX_S_SESSION_OPENED,
+                "SessionOpened",
+                &(*response_id, *session_id),
+            ),
+            ExitMessage {
+                session_id,
+                exit_value,
+            } => serializer.serialize_newtype_variant(
+                "Response",
+                MUX_S_EXIT_MESSAGE,
+                "ExitMessage",
+                &(*session_id, *exit_value),
+            ),
+            TtyAllocFail { session_id } => serializer.serialize_newtype_variant(
+                "Response",
+                MUX_S_TTY_ALLOC_FAIL,
+                "TtyAllocFail",
+                session_id,
+            ),
+            RemotePort {
+                response_id,
+                remote_port,
+            } => serializer.serialize_newtype_variant(
+                "Response",
+                MUX_S_REMOTE_PORT,
+                "RemotePort",
+                &(*response_id, *remote_port),
+            ),
+            #[cfg(feature = "proxy-client")]
+            Proxy { response_id } => {
+                serializer.serialize_newtype_variant("Response", MUX_S_PROXY, "Proxy", response_id)
+            }
+        }
+    }
+}
+
 struct ResponseVisitor;
 impl<'de> Visitor<'de> for ResponseVisitor {
     type Value = Response;
@@ -119,6 +234,11 @@ impl<'de> Visitor<'de> for ResponseVisitor {
                     remote_port: tup.1,
                 })
             }
+            #[cfg(feature = "proxy-client")]
+            MUX_S_PROXY => {
+                let response_id: u32 = accessor.newtype_variant_seed(PhantomData)?;
+                Ok(Response::Proxy { response_id })
+            }
             _ => Err(A::Error::custom("Unexpected packet type")),
         }
     }
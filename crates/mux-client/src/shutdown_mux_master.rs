@@ -1,8 +1,16 @@
 #![forbid(unsafe_code)]
 
-use crate::{constants, request::Request, Error, ErrorExt, Response, Result};
+use crate::{
+    constants,
+    request::{Fwd, Request},
+    Error, ErrorExt, Response, Result,
+};
 
-use std::{io::Read, io::Write, os::unix::net::UnixStream, path::Path};
+use std::{
+    io::{ErrorKind, Read, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+};
 
 use serde::{Deserialize, Serialize};
 use ssh_format::{from_bytes, Serializer};
@@ -13,7 +21,7 @@ struct Connection {
 }
 
 impl Connection {
-    fn write(&mut self, value: &Request) -> Result<()> {
+    fn write<T: Serialize>(&mut self, value: &T) -> Result<()> {
         let serializer = &mut self.serializer;
 
         serializer.reset_counter();
@@ -84,6 +92,16 @@ impl Connection {
         Self::new(UnixStream::connect(path)?).exchange_hello()
     }
 
+    /// Create from a connection that already exchanged hello.
+    ///
+    /// `raw_conn` would be set to blocking mode, since it might come
+    /// from `tokio::net::UnixStream::into_std`.
+    fn from_established(raw_conn: UnixStream) -> Result<Self> {
+        raw_conn.set_nonblocking(false)?;
+
+        Ok(Self::new(raw_conn))
+    }
+
     fn new(raw_conn: UnixStream) -> Self {
         Self {
             raw_conn,
@@ -124,6 +142,89 @@ impl Connection {
             )),
         }
     }
+
+    /// Request the master to close the port forwarding.
+    fn request_close_fwd(&mut self, fwd: &Fwd<'_>) -> Result<()> {
+        use Response::*;
+
+        let request_id = 0;
+        let (fwd_mode, listen_socket, connect_socket) = fwd.as_serializable();
+        self.write(&(
+            Request::CloseFwd {
+                request_id,
+                fwd_mode,
+            },
+            listen_socket,
+            connect_socket,
+        ))?;
+
+        match self.read_response()? {
+            Ok { response_id } => {
+                Self::check_response_id(request_id, response_id)?;
+                Result::Ok(())
+            }
+            PermissionDenied {
+                response_id,
+                reason,
+            } => {
+                Self::check_response_id(request_id, response_id)?;
+                Err(Error::PermissionDenied(reason))
+            }
+            Failure {
+                response_id,
+                reason,
+            } => {
+                Self::check_response_id(request_id, response_id)?;
+                Err(Error::RequestFailure(reason))
+            }
+            response => Err(Error::invalid_server_response(
+                &"Ok, PermissionDenied or Failure",
+                &response,
+            )),
+        }
+    }
+
+    /// Request the master to terminate immediately.
+    fn request_terminate(&mut self) -> Result<()> {
+        use Response::*;
+
+        let request_id = 0;
+        self.write(&Request::Terminate { request_id })?;
+
+        let response = match self.read_response() {
+            Result::Ok(response) => response,
+            // The master might terminate before replying
+            Err(Error::IOError(io_err)) if io_err.kind() == ErrorKind::UnexpectedEof => {
+                return Result::Ok(())
+            }
+            Err(err) => return Err(err),
+        };
+
+        match response {
+            Ok { response_id } => {
+                Self::check_response_id(request_id, response_id)?;
+                Result::Ok(())
+            }
+            PermissionDenied {
+                response_id,
+                reason,
+            } => {
+                Self::check_response_id(request_id, response_id)?;
+                Err(Error::PermissionDenied(reason))
+            }
+            Failure {
+                response_id,
+                reason,
+            } => {
+                Self::check_response_id(request_id, response_id)?;
+                Err(Error::RequestFailure(reason))
+            }
+            response => Err(Error::invalid_server_response(
+                &"Ok, PermissionDenied or Failure",
+                &response,
+            )),
+        }
+    }
 }
 
 /// Request the master to stop accepting new multiplexing requests
@@ -135,15 +236,35 @@ pub fn shutdown_mux_master<P: AsRef<Path>>(path: P) -> Result<()> {
 }
 
 pub(crate) fn shutdown_mux_master_from(raw_conn: UnixStream) -> Result<()> {
-    Connection::new(raw_conn).request_stop_listening()
+    Connection::from_established(raw_conn)?.request_stop_listening()
+}
+
+/// Request the master to terminate immediately (`ssh -O exit`).
+///
+/// **Only suitable to use in `Drop::drop`.**
+pub fn terminate_mux_master<P: AsRef<Path>>(path: P) -> Result<()> {
+    Connection::connect(path)?.request_terminate()
+}
+
+pub(crate) fn terminate_mux_master_from(raw_conn: UnixStream) -> Result<()> {
+    Connection::from_established(raw_conn)?.request_terminate()
+}
+
+pub(crate) fn close_fwd_from(raw_conn: UnixStream, fwd: &Fwd<'_>) -> Result<()> {
+    Connection::from_established(raw_conn)?.request_close_fwd(fwd)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::shutdown_mux_master;
+    use super::{shutdown_mux_master, terminate_mux_master};
 
     #[test]
     fn test_sync_request_stop_listening() {
         shutdown_mux_master("/tmp/openssh-mux-client-test.socket").unwrap();
     }
+
+    #[test]
+    fn test_sync_terminate_mux_master() {
+        terminate_mux_master("/tmp/openssh-mux-client-test.socket").unwrap();
+    }
 }
@@ -0,0 +1,43 @@
+#![forbid(unsafe_code)]
+
+use super::{Connection, Error, ErrorExt, Result};
+
+use std::io::ErrorKind;
+
+/// Stdio forwarding established by [`Connection::open_stdio_forward`].
+///
+/// NOTE that once `EstablishedStdioForward` is dropped, the forwarding
+/// would be torn down.
+///
+/// # Cancel safety
+///
+/// All methods of this struct is not cancellation safe.
+#[derive(Debug)]
+pub struct EstablishedStdioForward {
+    pub(super) conn: Connection,
+    pub(super) session_id: u32,
+}
+impl EstablishedStdioForward {
+    /// Return session id allocated by the ssh mux server.
+    pub fn session_id(&self) -> u32 {
+        self.session_id
+    }
+
+    /// Wait for the forwarding to be torn down, which happens when
+    /// either side closes the forwarded connection.
+    ///
+    /// Return `Self` on error so that you can handle the error and restart
+    /// the operation.
+    pub async fn wait(mut self) -> Result<(), (Error, Self)> {
+        // The ssh mux server does not send anything after the session is
+        // opened, it simply closes the connection once it is done.
+        match self.conn.read_response().await {
+            Ok(response) => Err((
+                Error::invalid_server_response(&"Connection closed", &response),
+                self,
+            )),
+            Err(Error::IOError(io_err)) if io_err.kind() == ErrorKind::UnexpectedEof => Ok(()),
+            Err(err) => Err((err, self)),
+        }
+    }
+}
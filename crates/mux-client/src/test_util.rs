@@ -0,0 +1,446 @@
+//! In-process mock of the ssh mux master, for testing code that uses
+//! [`Connection`](crate::Connection) without ssh or sshd.
+//!
+//! The mock speaks protocol 4 over a unix socket and replies to each
+//! request with the responses returned by a user-supplied handler, which
+//! makes it possible to script error paths deterministically.
+
+use crate::{
+    constants,
+    mux_server::{MuxRequest, ServerConnection},
+    Response,
+};
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+};
+
+use tokio::{net::UnixListener, spawn, task::JoinHandle};
+
+/// Request received by [`MockMuxMaster`].
+pub type MockRequest = MuxRequest;
+
+impl MockRequest {
+    /// Responses of a well-behaved ssh mux master:
+    ///
+    ///  - [`Response::Hello`] with protocol 4 for [`MockRequest::Hello`];
+    ///  - [`Response::Alive`] with pid of the current process for
+    ///    [`MockRequest::AliveCheck`];
+    ///  - [`Response::SessionOpened`] followed by [`Response::ExitMessage`]
+    ///    with exit value `0` for [`MockRequest::NewSession`];
+    ///  - [`Response::SessionOpened`] for [`MockRequest::NewStdioFwd`];
+    ///  - [`Response::Ok`] for the rest.
+    pub fn default_responses(&self) -> Vec<Response> {
+        use MuxRequest::*;
+
+        match self {
+            Hello { .. } => vec![Response::Hello {
+                version: constants::SSHMUX_VER,
+            }],
+            AliveCheck { request_id } => vec![Response::Alive {
+                response_id: *request_id,
+                server_pid: process::id(),
+            }],
+            NewSession { request_id, .. } => vec![
+                Response::SessionOpened {
+                    response_id: *request_id,
+                    session_id: 0,
+                },
+                Response::ExitMessage {
+                    session_id: 0,
+                    exit_value: 0,
+                },
+            ],
+            NewStdioFwd { request_id, .. } => vec![Response::SessionOpened {
+                response_id: *request_id,
+                session_id: 0,
+            }],
+            Terminate { request_id }
+            | OpenFwd { request_id, .. }
+            | CloseFwd { request_id, .. }
+            | StopListening { request_id } => vec![Response::Ok {
+                response_id: *request_id,
+            }],
+        }
+    }
+}
+
+type Handler = dyn Fn(MockRequest) -> Vec<Response> + Send + Sync;
+
+/// Mock ssh mux master listening on a unix socket.
+///
+/// For every request received, it calls the handler and sends back
+/// all the responses it returns.
+///
+/// It stops accepting new connections and removes the socket once dropped.
+#[derive(Debug)]
+pub struct MockMuxMaster {
+    path: PathBuf,
+    task: JoinHandle<()>,
+}
+
+impl MockMuxMaster {
+    /// Listen on `path` and serve in a newly spawned tokio task.
+    ///
+    /// Pass [`MockRequest::default_responses`] as `handler` to
+    /// mock a well-behaved ssh mux master.
+    pub fn bind<P, F>(path: P, handler: F) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+        F: Fn(MockRequest) -> Vec<Response> + Send + Sync + 'static,
+    {
+        let path = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&path)?;
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let task = spawn(async move {
+            while let Ok((stream, _addr)) = listener.accept().await {
+                let handler = handler.clone();
+
+                spawn(async move {
+                    // The connection is simply closed on any error.
+                    let _ = serve(ServerConnection::new(stream), &*handler).await;
+                });
+            }
+        });
+
+        Ok(Self { path, task })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for MockMuxMaster {
+    fn drop(&mut self) {
+        self.task.abort();
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+async fn serve(mut conn: ServerConnection, handler: &Handler) -> io::Result<()> {
+    loop {
+        let request = match conn.read_request().await {
+            Ok(request) => request,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break Ok(()),
+            Err(err) => break Err(err),
+        };
+
+        for response in handler(request) {
+            conn.write(&response).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Connection, Error, ForwardGuard, ForwardRegistry, ForwardType, Session, SessionStatus,
+        Socket,
+    };
+
+    use std::{
+        borrow::Cow, convert::TryInto, env, fs::File, io::Read, num::NonZeroU32,
+        os::unix::io::AsRawFd, sync::Mutex,
+    };
+
+    use tokio::{io::AsyncWriteExt, task::spawn_blocking};
+    use tokio_pipe::pipe;
+
+    fn socket_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!(
+            "openssh-mux-client-mock-{}-{}.socket",
+            process::id(),
+            name
+        ))
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_mock_alive_check() {
+        let master =
+            MockMuxMaster::bind(socket_path("alive"), |request| request.default_responses())
+                .unwrap();
+
+        let mut conn = Connection::connect(master.path()).await.unwrap();
+        assert_eq!(conn.send_alive_check().await.unwrap().get(), process::id());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_mock_unsupported_mux_protocol() {
+        let master = MockMuxMaster::bind(socket_path("unsupported"), |request| match request {
+            MockRequest::Hello { .. } => vec![Response::Hello { version: 3 }],
+            request => request.default_responses(),
+        })
+        .unwrap();
+
+        assert_matches!(
+            Connection::connect(master.path()).await,
+            Err(Error::UnsupportedMuxProtocol)
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_mock_unmatched_request_id() {
+        let master = MockMuxMaster::bind(socket_path("unmatched"), |request| match request {
+            MockRequest::AliveCheck { request_id } => vec![Response::Alive {
+                response_id: request_id + 1,
+                server_pid: 1,
+            }],
+            request => request.default_responses(),
+        })
+        .unwrap();
+
+        let mut conn = Connection::connect(master.path()).await.unwrap();
+        assert_matches!(
+            conn.send_alive_check().await,
+            Err(Error::UnmatchedRequestId)
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_mock_permission_denied() {
+        let master = MockMuxMaster::bind(socket_path("denied"), |request| {
+            match request.request_id() {
+                Some(response_id) => vec![Response::PermissionDenied {
+                    response_id,
+                    reason: "denied".into(),
+                }],
+                None => request.default_responses(),
+            }
+        })
+        .unwrap();
+
+        let mut conn = Connection::connect(master.path()).await.unwrap();
+        assert_matches!(
+            conn.request_stop_listening().await,
+            Err(Error::PermissionDenied(reason)) if &*reason == "denied"
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_mock_new_session() {
+        let master = MockMuxMaster::bind(socket_path("session"), |request| match request {
+            MockRequest::NewSession {
+                request_id,
+                session,
+                fds,
+            } => {
+                assert_eq!(session.cmd.into_inner(), b"echo");
+                assert_eq!(session.env.len(), 1);
+                assert_eq!(session.env[0].into_inner(), b"A=b");
+                assert_eq!(fds.len(), 3);
+
+                let mut stdin = File::from(fds.into_iter().next().unwrap());
+                let mut buffer = [0_u8; 5];
+                stdin.read_exact(&mut buffer).unwrap();
+                assert_eq!(&buffer, b"hello");
+
+                vec![
+                    Response::SessionOpened {
+                        response_id: request_id,
+                        session_id: 2,
+                    },
+                    Response::TtyAllocFail { session_id: 2 },
+                    Response::ExitMessage {
+                        session_id: 2,
+                        exit_value: 3,
+                    },
+                ]
+            }
+            request => request.default_responses(),
+        })
+        .unwrap();
+
+        let conn = Connection::connect(master.path()).await.unwrap();
+
+        let env = [Cow::Borrowed("A=b".try_into().unwrap())];
+        let session = Session::builder()
+            .cmd(Cow::Borrowed("echo".try_into().unwrap()))
+            .env(Cow::Borrowed(&env))
+            .build();
+
+        // pipe() returns (PipeRead, PipeWrite)
+        let (stdin_read, mut stdin_write) = pipe().unwrap();
+        stdin_write.write_all(b"hello").await.unwrap();
+
+        let established_session = conn
+            .open_new_session(
+                &session,
+                &[
+                    stdin_read.as_raw_fd(),
+                    io::stdout().as_raw_fd(),
+                    io::stderr().as_raw_fd(),
+                ],
+            )
+            .await
+            .unwrap();
+
+        let established_session = match established_session.wait().await.unwrap() {
+            SessionStatus::TtyAllocFail(established_session) => established_session,
+            session_status => panic!("Unexpected {:#?}", session_status),
+        };
+        assert_matches!(
+            established_session.wait().await.unwrap(),
+            SessionStatus::Exited {
+                exit_value: Some(3)
+            }
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_mock_port_forward() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let requests_cloned = requests.clone();
+
+        let master = MockMuxMaster::bind(socket_path("forward"), move |request| {
+            let responses = match &request {
+                MockRequest::OpenFwd {
+                    request_id,
+                    forward_type: Some(ForwardType::Remote),
+                    ..
+                } => vec![Response::RemotePort {
+                    response_id: *request_id,
+                    remote_port: 2345,
+                }],
+                MockRequest::CloseFwd { request_id, .. } => vec![Response::Failure {
+                    response_id: *request_id,
+                    reason: "no such forwarding".into(),
+                }],
+                request => request.default_responses(),
+            };
+            requests_cloned.lock().unwrap().push(request);
+            responses
+        })
+        .unwrap();
+
+        let mut conn = Connection::connect(master.path()).await.unwrap();
+
+        let listen_socket = Socket::TcpSocket {
+            port: 0,
+            host: "127.0.0.1".into(),
+        };
+        let connect_socket = Socket::UnixSocket {
+            path: Path::new("/tmp/socket").into(),
+        };
+
+        assert_eq!(
+            conn.request_port_forward(ForwardType::Remote, &listen_socket, &connect_socket)
+                .await
+                .unwrap(),
+            NonZeroU32::new(2345)
+        );
+        assert_eq!(
+            conn.request_port_forward(ForwardType::Local, &listen_socket, &connect_socket)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_matches!(
+            conn.close_port_forward(ForwardType::Local, &listen_socket, &connect_socket)
+                .await,
+            Err(Error::RequestFailure(reason)) if &*reason == "no such forwarding"
+        );
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 4);
+        assert_matches!(&requests[0], MockRequest::Hello { version: 4 });
+        for request in &requests[1..] {
+            match request {
+                MockRequest::OpenFwd {
+                    listen_socket: listen,
+                    connect_socket: connect,
+                    ..
+                }
+                | MockRequest::CloseFwd {
+                    listen_socket: listen,
+                    connect_socket: connect,
+                    ..
+                } => {
+                    assert_eq!(listen, &listen_socket);
+                    assert_eq!(connect, &connect_socket);
+                }
+                request => panic!("Unexpected {:#?}", request),
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_mock_forward_registry() {
+        let close_fwd_count = Arc::new(Mutex::new(0));
+        let close_fwd_count_cloned = close_fwd_count.clone();
+
+        let master = MockMuxMaster::bind(socket_path("registry"), move |request| match request {
+            MockRequest::OpenFwd {
+                request_id,
+                forward_type: Some(ForwardType::Remote),
+                ..
+            } => vec![Response::RemotePort {
+                response_id: request_id,
+                remote_port: 2345,
+            }],
+            MockRequest::CloseFwd { request_id, .. } => {
+                *close_fwd_count_cloned.lock().unwrap() += 1;
+                vec![Response::Failure {
+                    response_id: request_id,
+                    reason: "no such forwarding".into(),
+                }]
+            }
+            request => request.default_responses(),
+        })
+        .unwrap();
+
+        let listen_socket = Socket::TcpSocket {
+            port: 0,
+            host: "127.0.0.1".into(),
+        };
+        let connect_socket = Socket::UnixSocket {
+            path: Path::new("/tmp/socket").into(),
+        };
+
+        let mut registry = ForwardRegistry::new();
+
+        for (i, &forward_type) in [ForwardType::Local, ForwardType::Remote].iter().enumerate() {
+            let guard = ForwardGuard::request_port_forward(
+                Connection::connect(master.path()).await.unwrap(),
+                forward_type,
+                listen_socket.clone(),
+                connect_socket.clone(),
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(registry.register(guard), i);
+        }
+
+        let forwards = registry.list();
+        assert_eq!(forwards.len(), 2);
+        assert_eq!(forwards[0].forward_type(), Some(ForwardType::Local));
+        assert_eq!(forwards[0].allocated_port(), None);
+        assert_eq!(forwards[1].forward_type(), Some(ForwardType::Remote));
+        assert_eq!(forwards[1].allocated_port(), NonZeroU32::new(2345));
+        for forward in forwards {
+            assert_eq!(forward.listen_socket(), &listen_socket);
+            assert_eq!(forward.connect_socket(), Some(&connect_socket));
+        }
+
+        // The guard is returned on error.
+        let (err, guard) = registry.unregister(0).close().await.unwrap_err();
+        assert_matches!(err, Error::RequestFailure(reason) if &*reason == "no such forwarding");
+        assert_eq!(guard.forward_type(), Some(ForwardType::Local));
+        assert_eq!(*close_fwd_count.lock().unwrap(), 1);
+
+        // Dropping a guard closes the forwarding synchronously, which
+        // would block the mock running on the same thread.
+        spawn_blocking(move || drop(guard)).await.unwrap();
+        assert_eq!(*close_fwd_count.lock().unwrap(), 2);
+
+        assert_eq!(registry.list().len(), 1);
+        spawn_blocking(move || drop(registry)).await.unwrap();
+        assert_eq!(*close_fwd_count.lock().unwrap(), 3);
+    }
+}
@@ -1,9 +1,9 @@
-use std::io;
+use std::{io, sync::Arc};
 
 use thiserror::Error as ThisError;
 use tokio::task::JoinError;
 
-use crate::{OpenFailure, SshFormatError};
+use crate::{ErrorCode, OpenFailure, SshFormatError};
 
 #[derive(Debug, ThisError)]
 #[non_exhaustive]
@@ -25,7 +25,17 @@ pub enum Error {
 
     /// Failed to open channel
     #[error(transparent)]
-    ChannelOpenFailure(#[from] OpenFailure),
+    ChannelOpenFailure(OpenFailure),
+
+    /// sshd failed to connect to the target of the channel,
+    /// e.g. nothing is listening on the remote socket.
+    #[error("sshd failed to connect to the target: {0}")]
+    ConnectFailed(OpenFailure),
+
+    /// sshd is configured to refuse the channel,
+    /// e.g. forwarding is disabled in its config.
+    #[error("sshd prohibits opening the channel: {0}")]
+    AdministrativelyProhibited(OpenFailure),
 
     /// Unexpected channel state
     #[error("Expected {expected_state} but actual state is {actual_state}")]
@@ -50,12 +60,42 @@ pub enum Error {
     #[error("Receive unexpected response for channel request")]
     UnexpectedRequestResponse,
 
+    /// sshd refused the channel request
+    #[error("sshd refused the channel request {0}")]
+    ChannelRequestFailure(&'static &'static str),
+
+    /// sshd refused the global request
+    #[error("sshd refused the global request {0}")]
+    GlobalRequestFailure(&'static &'static str),
+
+    /// sshd did not reply to consecutive `keepalive@openssh.com` requests,
+    /// the connection is considered dead.
+    #[error("sshd did not reply to {0} consecutive keepalive requests")]
+    KeepaliveTimeout(u32),
+
+    /// Connection to sshd is broken, shared by all channels still open
+    /// at that time.
+    #[error("Connection to sshd is broken: {0}")]
+    ConnectionBroken(Arc<Error>),
+
     /// Tokio task failed
     #[error("tokio task failed: {0}")]
     JoinError(#[from] JoinError),
 }
 
+impl From<OpenFailure> for Error {
+    fn from(failure: OpenFailure) -> Self {
+        match failure.error_code {
+            ErrorCode::ConnectFailed => Error::ConnectFailed(failure),
+            ErrorCode::AdministrativelyProhibited => Error::AdministrativelyProhibited(failure),
+            _ => Error::ChannelOpenFailure(failure),
+        }
+    }
+}
+
 impl From<Error> for io::Error {
+    // `io::Error::other` requires rust 1.74.
+    #[allow(clippy::io_other_error)]
     fn from(err: Error) -> io::Error {
         match err {
             Error::IOError(io_error) => io_error,
@@ -20,3 +20,7 @@ def_constants!(SSH_MSG_CHANNEL_SUCCESS, 99);
 def_constants!(SSH_MSG_CHANNEL_FAILURE, 100);
 
 pub(crate) const SSH_EXTENDED_DATA_STDERR: u32 = 1;
+
+pub(crate) const SSH_OPEN_ADMINISTRATIVELY_PROHIBITED: u32 = 1;
+pub(crate) const SSH_OPEN_UNKNOWN_CHANNEL_TYPE: u32 = 3;
+pub(crate) const SSH_OPEN_RESOURCE_SHORTAGE: u32 = 4;
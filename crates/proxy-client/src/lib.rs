@@ -4,8 +4,19 @@ pub use error::Error;
 pub use openssh_proxy_client_error as error;
 
 mod proxy_client;
-pub use proxy_client::ProxyClient;
+pub use proxy_client::{
+    ChannelConfig, ChannelInput, ChannelOutput, ChannelStream, Child, ForwardedStreamLocal,
+    ForwardedTcpIp, ForwardedX11, ProxyClient, ProxyClientBuilder, RemoteForwardListener, Session,
+    StreamLocalForwardListener,
+};
 
 mod constants;
 mod request;
+pub use request::{TerminalModes, WindowSize};
+
 mod response;
+pub use response::{ExitSignal, ExitStatus, SignalName};
+
+#[cfg(test)]
+#[macro_use]
+extern crate assert_matches;
@@ -0,0 +1,92 @@
+use std::{
+    num::{NonZeroU32, NonZeroUsize},
+    time::Duration,
+};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::{
+    create_read_task, create_write_task, keepalive::KeepaliveConfig, ChannelConfig, ProxyClient,
+    SharedData,
+};
+
+/// Builder of [`ProxyClient`].
+#[derive(Clone, Debug)]
+pub struct ProxyClientBuilder {
+    reusable_io_slice_cap: NonZeroUsize,
+    keepalive: Option<KeepaliveConfig>,
+    channel_config: ChannelConfig,
+    max_channels: Option<NonZeroU32>,
+}
+
+impl Default for ProxyClientBuilder {
+    fn default() -> Self {
+        Self {
+            reusable_io_slice_cap: NonZeroUsize::new(16).unwrap(),
+            keepalive: None,
+            channel_config: ChannelConfig::default(),
+            max_channels: None,
+        }
+    }
+}
+
+impl ProxyClientBuilder {
+    /// Determines how many `Bytes` can be sent in one syscall to reduce
+    /// overhead, default is 16.
+    pub fn reusable_io_slice_cap(&mut self, reusable_io_slice_cap: NonZeroUsize) -> &mut Self {
+        self.reusable_io_slice_cap = reusable_io_slice_cap;
+        self
+    }
+
+    /// Send `keepalive@openssh.com` every `interval`.
+    ///
+    /// If `max_missed` consecutive requests are not replied, the connection
+    /// is considered dead: all channels are aborted and
+    /// [`ProxyClient::close`] returns [`Error::KeepaliveTimeout`](crate::Error::KeepaliveTimeout),
+    /// which is wrapped in [`Error::ConnectionBroken`](crate::Error::ConnectionBroken)
+    /// if a channel still holds the error returned to it.
+    ///
+    /// Keepalive is disabled by default.
+    pub fn keepalive(&mut self, interval: Duration, max_missed: NonZeroU32) -> &mut Self {
+        self.keepalive = Some(KeepaliveConfig {
+            interval,
+            max_missed,
+        });
+        self
+    }
+
+    /// Default configuration of channels, including channels opened by
+    /// sshd.
+    pub fn channel_config(&mut self, channel_config: ChannelConfig) -> &mut Self {
+        self.channel_config = channel_config;
+        self
+    }
+
+    /// Max number of concurrent channels, including channels opened
+    /// by sshd.
+    ///
+    /// Once it is reached, opening a new channel waits until another
+    /// channel is closed, while channels opened by sshd are refused.
+    ///
+    /// The number of concurrent channels is unlimited by default.
+    pub fn max_channels(&mut self, max_channels: NonZeroU32) -> &mut Self {
+        self.max_channels = Some(max_channels);
+        self
+    }
+
+    /// Create the [`ProxyClient`] and spawn its tasks on the current
+    /// tokio runtime.
+    pub fn build<R, W>(&self, rx: R, tx: W) -> ProxyClient
+    where
+        R: AsyncRead + Send + 'static,
+        W: AsyncWrite + Send + 'static,
+    {
+        let shared_data = SharedData::new(self.channel_config, self.max_channels);
+
+        ProxyClient {
+            write_task: create_write_task(tx, shared_data.clone(), self.reusable_io_slice_cap),
+            read_task: create_read_task(rx, shared_data.clone(), self.keepalive),
+            shared_data,
+        }
+    }
+}
@@ -1,6 +1,4 @@
 use std::{
-    future::Future,
-    mem,
     num::NonZeroU64,
     sync::{
         atomic::{AtomicU64, Ordering::Relaxed},
@@ -9,8 +7,6 @@ use std::{
     task::{Context, Poll, Waker},
 };
 
-use futures_util::future::poll_fn;
-
 /// AwaitableAtomicU64.
 /// Can have multiple writer that adds to the counter but only one reader
 /// that decrements the counter.
@@ -55,7 +51,7 @@ impl AwaitableAtomicU64 {
         // just before point 2, we can register the waker here.
         //
         // Any [`AtomicU64::add`] called after point 2 will wake us up.
-        let prev_waker = mem::replace(&mut *guard, Some(cx.waker().clone()));
+        let prev_waker = guard.replace(cx.waker().clone());
 
         // Release lock
         drop(guard);
@@ -69,14 +65,6 @@ impl AwaitableAtomicU64 {
             Poll::Pending
         }
     }
-
-    /// Return the atomic value if it is non-zero,
-    /// or wait until it is changed to non-zero.
-    ///
-    /// It will set the atomic to 0 atomically before returning.
-    pub(crate) fn wait_until_non_zero(&self) -> impl Future<Output = NonZeroU64> + '_ {
-        poll_fn(move |cx| self.poll_until_non_zero(cx))
-    }
 }
 
 /// For the writers
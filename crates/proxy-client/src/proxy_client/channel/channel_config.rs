@@ -0,0 +1,84 @@
+use std::num::NonZeroU32;
+
+/// Same as the default window size of openssh.
+const DEFAULT_WINDOW_SIZE: u32 = 2 * 1024 * 1024;
+
+/// Same as the default max packet size of openssh.
+const DEFAULT_MAX_PACKET_SIZE: u32 = 32 * 1024;
+
+/// Flow control configuration of a channel.
+///
+/// The default is the same as openssh: 2 MiB window, 32 KiB max packet
+/// size and no adaptive window.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ChannelConfig {
+    window_size: NonZeroU32,
+    max_packet_size: NonZeroU32,
+    /// `None` if adaptive window is disabled.
+    max_window_size: Option<NonZeroU32>,
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChannelConfig {
+    pub fn new() -> Self {
+        Self {
+            window_size: NonZeroU32::new(DEFAULT_WINDOW_SIZE).unwrap(),
+            max_packet_size: NonZeroU32::new(DEFAULT_MAX_PACKET_SIZE).unwrap(),
+            max_window_size: None,
+        }
+    }
+
+    /// Number of bytes sshd can send before waiting for the window
+    /// to be extended.
+    ///
+    /// The window is only extended as the data is read from
+    /// [`ChannelOutput`](super::ChannelOutput), so it also bounds
+    /// the number of bytes buffered for the channel.
+    ///
+    /// Larger window allows higher throughput on high-latency link
+    /// at the cost of more memory.
+    pub fn window_size(&mut self, window_size: NonZeroU32) -> &mut Self {
+        self.window_size = window_size;
+        self
+    }
+
+    /// Max size of a single data packet sshd can send.
+    pub fn max_packet_size(&mut self, max_packet_size: NonZeroU32) -> &mut Self {
+        self.max_packet_size = max_packet_size;
+        self
+    }
+
+    /// Grow the window automatically up to `max_window_size` while
+    /// it limits the throughput.
+    ///
+    /// The window is considered to be limiting if half of it is consumed
+    /// within one second, in which case it is doubled.
+    ///
+    /// If `max_window_size` is not larger than the window size, then
+    /// the adaptive window is disabled.
+    pub fn adaptive_window(&mut self, max_window_size: NonZeroU32) -> &mut Self {
+        self.max_window_size = Some(max_window_size);
+        self
+    }
+
+    pub(in crate::proxy_client) fn get_window_size(&self) -> u32 {
+        self.window_size.get()
+    }
+
+    pub(in crate::proxy_client) fn get_max_packet_size(&self) -> u32 {
+        self.max_packet_size.get()
+    }
+
+    pub(in crate::proxy_client) fn get_max_window_size(&self) -> u32 {
+        self.max_window_size
+            .map_or(self.window_size, |max_window_size| {
+                max_window_size.max(self.window_size)
+            })
+            .get()
+    }
+}
@@ -1,8 +1,10 @@
 use std::{
     convert::TryInto,
+    future::Future,
     io, mem,
     num::{NonZeroU32, NonZeroU64},
     pin::Pin,
+    sync::atomic::Ordering::Relaxed,
     task::{Context, Poll},
 };
 
@@ -41,6 +43,32 @@ pub struct ChannelInput {
 }
 
 impl ChannelInput {
+    pub(in crate::proxy_client) fn new(
+        channel_ref: ChannelRef,
+        max_packet_size: NonZeroU32,
+    ) -> Self {
+        let token = channel_ref
+            .shared_data
+            .get_cancellation_token()
+            .clone()
+            .cancelled_owned();
+
+        Self {
+            channel_ref,
+            max_packet_size,
+            curr_sender_win: 0,
+            pending_bytes: Vec::new(),
+            pending_len: 0,
+            buffer: BytesMut::new(),
+            token,
+        }
+    }
+
+    /// Id of the channel this input belongs to.
+    pub fn channel_id(&self) -> u32 {
+        self.channel_ref.channel_id()
+    }
+
     fn add_pending_byte(self: Pin<&mut Self>, bytes: Bytes) {
         let this = self.project();
 
@@ -60,7 +88,7 @@ impl ChannelInput {
     fn create_data_transfer_header(self: Pin<&mut Self>, n: u32) -> Result<Bytes, Error> {
         let this = self.project();
 
-        let channel_id = this.channel_ref.channel_id();
+        let channel_id = this.channel_ref.recipient_channel();
 
         let buffer = this.buffer;
 
@@ -73,14 +101,36 @@ impl ChannelInput {
         res
     }
 
+    /// Return error if sshd can no longer write to the process,
+    /// e.g. the process has closed its stdin, or if the channel
+    /// is closed by [`ProxyClient::close_with`](crate::ProxyClient::close_with)
+    /// or the connection is broken.
+    fn check_writable(&self) -> Result<(), Error> {
+        let shared_data = &self.channel_ref.shared_data;
+        if shared_data.get_cancellation_token().is_cancelled() {
+            return Err(shared_data.get_terminal_error());
+        }
+
+        let channel_data = &self.channel_ref.channel_data;
+
+        if channel_data.eow_received.load(Relaxed) || channel_data.close_sent.load(Relaxed) {
+            Err(io::Error::from(io::ErrorKind::BrokenPipe).into())
+        } else {
+            Ok(())
+        }
+    }
+
     fn try_flush(mut self: Pin<&mut Self>) -> Result<(), Error> {
+        self.check_writable()?;
+
         let this = self.as_mut().project();
 
         // Maximum number of bytes we can write to
         let max = this
             .max_packet_size
             .get()
-            .min((*this.curr_sender_win).try_into().unwrap_or(u32::MAX));
+            .min((*this.curr_sender_win).try_into().unwrap_or(u32::MAX))
+            .min((*this.pending_len).try_into().unwrap_or(u32::MAX));
 
         if max == 0 || this.pending_bytes.is_empty() {
             return Ok(());
@@ -161,9 +211,17 @@ impl Sink<Bytes> for ChannelInput {
     type Error = Error;
 
     fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.check_writable()?;
+
         let this = self.project();
 
         if *this.curr_sender_win == 0 {
+            // Wake up once the connection is broken, in which case
+            // the window would never be extended.
+            if this.token.poll(cx).is_ready() {
+                return Poll::Ready(Err(this.channel_ref.shared_data.get_terminal_error()));
+            }
+
             *this.curr_sender_win = ready!(this
                 .channel_ref
                 .channel_data
@@ -176,6 +234,8 @@ impl Sink<Bytes> for ChannelInput {
     }
 
     fn start_send(mut self: Pin<&mut Self>, bytes: Bytes) -> Result<(), Self::Error> {
+        self.check_writable()?;
+
         if !bytes.is_empty() {
             self.as_mut().add_pending_byte(bytes);
 
@@ -288,7 +348,12 @@ impl ChannelInput {
     fn send_eof_packet(self: Pin<&mut Self>) {
         let this = self.project();
 
-        let channel_id = this.channel_ref.channel_id();
+        if this.channel_ref.channel_data.close_sent.load(Relaxed) {
+            // Eof must not be sent after close.
+            return;
+        }
+
+        let channel_id = this.channel_ref.recipient_channel();
 
         let buffer = this.buffer;
         debug_assert!(buffer.is_empty());
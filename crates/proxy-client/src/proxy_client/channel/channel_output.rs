@@ -14,7 +14,15 @@ use futures_util::{
 use tokio::io::{AsyncBufRead, AsyncRead, ReadBuf};
 
 use super::{ChannelRef, MpscBytesChannel};
+use crate::Error;
 
+/// Output of the channel.
+///
+/// If the connection to sshd is broken before eof is received,
+/// reading it via [`AsyncRead`] or [`AsyncBufRead`] fails with
+/// [`Error::ConnectionBroken`] instead of returning eof, while the
+/// [`Stream`] simply ends, after which the error can be retrieved
+/// by [`ChannelOutput::connection_error`].
 #[derive(Debug)]
 pub struct ChannelOutput {
     channel_ref: ChannelRef,
@@ -30,6 +38,34 @@ pub struct ChannelOutput {
 }
 
 impl ChannelOutput {
+    pub(in crate::proxy_client) fn new(
+        channel_ref: ChannelRef,
+        channel: Arc<MpscBytesChannel>,
+    ) -> Self {
+        Self {
+            channel_ref,
+            channel,
+            fifo: Vec::new(),
+            is_eof: false,
+        }
+    }
+
+    /// Id of the channel this output belongs to.
+    pub fn channel_id(&self) -> u32 {
+        self.channel_ref.channel_id()
+    }
+
+    /// Returns the error that broke the connection to sshd if the output
+    /// ends due to it instead of eof sent by sshd.
+    ///
+    /// Call it once the [`Stream`] returns `None` to distinguish the
+    /// two cases.
+    pub fn connection_error(&self) -> Option<Error> {
+        self.channel
+            .is_aborted()
+            .then(|| self.channel_ref.shared_data.get_terminal_error())
+    }
+
     /// If self.fifo is not empty, ret.
     /// Otherwise poll for data.
     fn poll_for_data(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
@@ -52,6 +88,8 @@ impl ChannelOutput {
     }
 }
 
+/// Ends on eof, or once the connection to sshd is broken, see
+/// [`ChannelOutput::connection_error`].
 impl Stream for ChannelOutput {
     type Item = Bytes;
 
@@ -60,7 +98,13 @@ impl Stream for ChannelOutput {
 
         // If self.is_eof == true, then self.fifo.pop() would return None.
         // Otherwise, it would return Some.
-        Poll::Ready(self.fifo.pop())
+        let bytes = self.fifo.pop();
+
+        if let Some(bytes) = &bytes {
+            self.channel_ref.release_receiver_window(bytes.len());
+        }
+
+        Poll::Ready(bytes)
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
@@ -78,15 +122,21 @@ impl AsyncBufRead for ChannelOutput {
     fn poll_fill_buf(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
         ready!(self.as_mut().poll_for_data(cx));
 
-        Poll::Ready(Ok(Pin::into_inner(self)
-            .fifo
-            .last()
-            .map(Deref::deref)
-            .unwrap_or(&[])))
+        let this = Pin::into_inner(self);
+
+        Poll::Ready(match this.fifo.last() {
+            Some(bytes) => Ok(bytes.deref()),
+            // Eof is caused by broken connection instead of sshd.
+            None if this.channel.is_aborted() => {
+                Err(this.channel_ref.shared_data.get_terminal_error().into())
+            }
+            None => Ok(&[]),
+        })
     }
 
     fn consume(mut self: Pin<&mut Self>, amt: usize) {
-        let fifo = &mut self.fifo;
+        let this = &mut *self;
+        let fifo = &mut this.fifo;
 
         if amt == 0 {
             return;
@@ -108,6 +158,8 @@ impl AsyncBufRead for ChannelOutput {
             // Afterwards, bytes contains [amt, len).
             let _: Bytes = bytes.split_to(amt);
         }
+
+        this.channel_ref.release_receiver_window(amt);
     }
 }
 impl AsyncRead for ChannelOutput {
@@ -116,13 +168,20 @@ impl AsyncRead for ChannelOutput {
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+
         loop {
             let remaining = buf.remaining();
             if remaining == 0 {
                 break Poll::Ready(Ok(()));
             }
 
-            let slice = ready!(self.as_mut().poll_fill_buf(cx))?;
+            let slice = match self.as_mut().poll_fill_buf(cx) {
+                Poll::Ready(res) => res?,
+                // Must not return Poll::Pending if any data is read.
+                Poll::Pending if buf.filled().len() != filled => break Poll::Ready(Ok(())),
+                Poll::Pending => break Poll::Pending,
+            };
             if slice.is_empty() {
                 break Poll::Ready(Ok(()));
             }
@@ -138,6 +197,10 @@ impl AsyncRead for ChannelOutput {
 
 impl Drop for ChannelOutput {
     fn drop(&mut self) {
+        // Drop the reader, any write to it will be ignored
+        // and its internal buffer/waker dropped.
+        let dropped = self.channel.drop_reader();
+
         // Decrease receivers_count.
         //
         // Once it is reduced to 0, the channel would not
@@ -151,8 +214,66 @@ impl Drop for ChannelOutput {
         // If prev_cnt, then the fetch_sub operatio underflows.
         debug_assert_ne!(prev_cnt, 0);
 
-        // Drop the reader, any write to it will be ignored
-        // and its internal buffer/waker dropped.
-        self.channel.drop_reader();
+        // Data dropped is considered as consumed, so that
+        // other receivers of the channel would not be blocked.
+        let unconsumed: usize = self.fifo.iter().map(Bytes::len).sum();
+        self.channel_ref
+            .release_receiver_window(unconsumed + dropped);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::{
+        fake_sshd::{connect, exec_session, open_session, put_string},
+        ChannelConfig, ProxyClient,
+    };
+    use crate::constants::*;
+
+    use std::num::NonZeroU32;
+
+    use bytes::BytesMut;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_slow_reader() {
+        let (client, mut sshd) = connect(&ProxyClient::builder());
+
+        let mut config = ChannelConfig::new();
+        config
+            .window_size(NonZeroU32::new(4096).unwrap())
+            .max_packet_size(NonZeroU32::new(1024).unwrap());
+
+        let (session, recipient_channel) = open_session(&client, &mut sshd, 0, 0, &config).await;
+        let mut child = exec_session(session, &mut sshd, 0, recipient_channel).await;
+        let mut stdout = child.stdout.take().unwrap();
+
+        // Use up the whole window while the reader is paused.
+        for _ in 0..4 {
+            sshd.write_data(recipient_channel, &[0; 1024]).await;
+        }
+
+        // The reply to a global request is sent after all the data
+        // above is handled, so no window adjustment is sent by then.
+        let mut buffer = BytesMut::new();
+        put_string(&mut buffer, b"unknown@example.com");
+        buffer.extend_from_slice(&[1]);
+        sshd.write_packet(SSH_MSG_GLOBAL_REQUEST, &buffer).await;
+
+        let (packet_type, _) = sshd.read_packet().await;
+        assert_eq!(packet_type, SSH_MSG_REQUEST_FAILURE);
+        assert_eq!(stdout.channel.buffered_len(), 4096);
+
+        // The window is extended by exactly the bytes consumed, once
+        // half of it is consumed.
+        let mut buffer = [1_u8; 2048];
+        stdout.read_exact(&mut buffer).await.unwrap();
+        assert!(buffer.iter().all(|byte| *byte == 0));
+
+        let (packet_type, payload) = sshd.read_packet().await;
+        assert_eq!(packet_type, SSH_MSG_CHANNEL_WINDOW_ADJUST);
+        let (channel, bytes_to_add): (u32, u32) = ssh_format::from_bytes(&payload).unwrap().0;
+        assert_eq!(channel, 0);
+        assert_eq!(bytes_to_add, 2048);
     }
 }
@@ -10,7 +10,7 @@ use strum::IntoStaticStr;
 
 use crate::{
     error::{Error, OpenFailure},
-    response::{ExitSignal, ExitStatus},
+    response::ExitStatus,
 };
 
 #[derive(Debug)]
@@ -24,7 +24,7 @@ struct Inner {
 
 /// Expected state transition:
 ///
-/// OpenChannelRequested => OpenChannelRequestConfirmed => ProcessExited | ProcessKilled => Consumed
+/// OpenChannelRequested => OpenChannelRequestConfirmed => ProcessExited | ChannelClosed | ChannelAborted => Consumed
 ///
 /// or
 ///
@@ -32,9 +32,10 @@ struct Inner {
 #[derive(Debug, IntoStaticStr)]
 enum State {
     /// Sent open channel request
-    OpenChannelRequested(OpenChannelRequestedInner),
+    OpenChannelRequested,
 
     OpenChannelRequestConfirmed {
+        recipient_channel: u32,
         max_packet_size: u32,
     },
 
@@ -42,45 +43,36 @@ enum State {
 
     ProcessExited(ExitStatus),
 
-    ProcessKilled(ExitSignal),
+    /// sshd closed the channel without sending the exit status
+    ChannelClosed,
+
+    /// The connection to sshd is broken before the channel is closed
+    ChannelAborted,
 
     Consumed,
 }
 
+/// Returned by `wait_for_process_exit` if the connection to sshd
+/// is broken before the channel is closed.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct ChannelAborted;
+
 #[derive(Debug)]
 pub(crate) enum OpenChannelRes {
     /// Ok and confirmed
     Confirmed {
+        /// Channel id allocated by sshd.
+        recipient_channel: u32,
         max_packet_size: u32,
     },
     Failed(OpenFailure),
 }
 
-#[derive(Debug)]
-pub(crate) enum ProcessStatus {
-    ProcessExited(ExitStatus),
-    ProcessKilled(ExitSignal),
-}
-
-#[derive(Copy, Clone, Debug)]
-pub(crate) struct OpenChannelRequestedInner {
-    pub(crate) init_receiver_win_size: u32,
-
-    /// The number of bytes `extend_window_size_packet` will extend
-    /// the receiver window size.
-    pub(crate) extend_window_size: u32,
-}
-
 /// For the channel users
 impl ChannelState {
-    /// * `extend_window_size_packet` - The packet to sent to expend window size.
-    ///   It should have all the data required.
-    pub(crate) fn new(init_receiver_win_size: u32, extend_window_size: u32) -> Self {
+    pub(crate) fn new() -> Self {
         Self(Mutex::new(Inner {
-            state: State::OpenChannelRequested(OpenChannelRequestedInner {
-                init_receiver_win_size,
-                extend_window_size,
-            }),
+            state: State::OpenChannelRequested,
             waker: None,
         }))
     }
@@ -95,14 +87,18 @@ impl ChannelState {
                 let mut guard = self.0 .0.lock().unwrap();
 
                 match guard.state {
-                    State::OpenChannelRequested { .. } => {
+                    State::OpenChannelRequested => {
                         ChannelState::install_new_waker(guard, cx);
 
                         Poll::Pending
                     }
-                    State::OpenChannelRequestConfirmed { max_packet_size } => {
-                        Poll::Ready(OpenChannelRes::Confirmed { max_packet_size })
-                    }
+                    State::OpenChannelRequestConfirmed {
+                        recipient_channel,
+                        max_packet_size,
+                    } => Poll::Ready(OpenChannelRes::Confirmed {
+                        recipient_channel,
+                        max_packet_size,
+                    }),
                     State::OpenChannelRequestFailed(..) => {
                         let prev_state = mem::replace(&mut guard.state, State::Consumed);
 
@@ -124,12 +120,17 @@ impl ChannelState {
     }
 
     /// Must be called after `wait_for_confirmation` returns
-    /// `OpenChannelRes::Confirmed`
-    pub(crate) fn wait_for_process_exit(&self) -> impl Future<Output = ProcessStatus> + '_ {
+    /// `OpenChannelRes::Confirmed`.
+    ///
+    /// Returns `Ok(None)` if sshd closed the channel without
+    /// sending the exit status.
+    pub(crate) fn wait_for_process_exit(
+        &self,
+    ) -> impl Future<Output = Result<Option<ExitStatus>, ChannelAborted>> + '_ {
         struct WaitForProcessExit<'a>(&'a ChannelState);
 
         impl Future for WaitForProcessExit<'_> {
-            type Output = ProcessStatus;
+            type Output = Result<Option<ExitStatus>, ChannelAborted>;
 
             fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
                 let mut guard = self.0 .0.lock().unwrap();
@@ -140,19 +141,16 @@ impl ChannelState {
 
                         Poll::Pending
                     }
-                    State::ProcessKilled(..) | State::ProcessExited(..) => {
+                    State::ProcessExited(..) | State::ChannelClosed | State::ChannelAborted => {
                         let prev_state = mem::replace(&mut guard.state, State::Consumed);
 
                         // Release lock
                         drop(guard);
 
                         Poll::Ready(match prev_state {
-                            State::ProcessExited(exit_status) => {
-                                ProcessStatus::ProcessExited(exit_status)
-                            }
-                            State::ProcessKilled(exit_signal) => {
-                                ProcessStatus::ProcessKilled(exit_signal)
-                            }
+                            State::ProcessExited(exit_status) => Ok(Some(exit_status)),
+                            State::ChannelClosed => Ok(None),
+                            State::ChannelAborted => Err(ChannelAborted),
                             _ => unreachable!(),
                         })
                     }
@@ -165,7 +163,7 @@ impl ChannelState {
     }
 
     fn install_new_waker(mut guard: MutexGuard<'_, Inner>, cx: &mut Context<'_>) {
-        let prev_waker = mem::replace(&mut guard.waker, Some(cx.waker().clone()));
+        let prev_waker = guard.waker.replace(cx.waker().clone());
 
         // Release lock
         drop(guard);
@@ -177,23 +175,24 @@ impl ChannelState {
 /// For the channel read task.
 impl ChannelState {
     /// Must be only called once by the channel read task.
-    pub(crate) fn set_channel_open_res(
-        &self,
-        res: OpenChannelRes,
-    ) -> Result<OpenChannelRequestedInner, Error> {
+    pub(crate) fn set_channel_open_res(&self, res: OpenChannelRes) -> Result<(), Error> {
         let mut guard = self.0.lock().unwrap();
 
-        if let State::OpenChannelRequested(inner) = guard.state {
+        if let State::OpenChannelRequested = guard.state {
             guard.state = match res {
-                OpenChannelRes::Confirmed { max_packet_size } => {
-                    State::OpenChannelRequestConfirmed { max_packet_size }
-                }
+                OpenChannelRes::Confirmed {
+                    recipient_channel,
+                    max_packet_size,
+                } => State::OpenChannelRequestConfirmed {
+                    recipient_channel,
+                    max_packet_size,
+                },
                 OpenChannelRes::Failed(err) => State::OpenChannelRequestFailed(err),
             };
 
             Self::wakeup(guard);
 
-            Ok(inner)
+            Ok(())
         } else {
             Err(Error::UnexpectedChannelState {
                 expected_state: &"OpenChannelRequested",
@@ -203,23 +202,42 @@ impl ChannelState {
     }
 
     /// Must be called after `set_channel_open_res`.
-    pub(crate) fn set_channel_process_status(&self, status: ProcessStatus) -> Result<(), Error> {
+    ///
+    /// Only the first exit status is recorded, duplicates sent by sshd
+    /// are ignored.
+    pub(crate) fn set_channel_process_status(&self, status: ExitStatus) {
         let mut guard = self.0.lock().unwrap();
 
         if let State::OpenChannelRequestConfirmed { .. } = guard.state {
-            guard.state = match status {
-                ProcessStatus::ProcessExited(exit_status) => State::ProcessExited(exit_status),
-                ProcessStatus::ProcessKilled(exit_signal) => State::ProcessKilled(exit_signal),
-            };
+            guard.state = State::ProcessExited(status);
+
+            Self::wakeup(guard);
+        }
+    }
+
+    /// Must be called after `set_channel_open_res`.
+    ///
+    /// If the exit status is not yet received, then `wait_for_process_exit`
+    /// would return `None`.
+    pub(crate) fn set_channel_closed(&self) {
+        let mut guard = self.0.lock().unwrap();
+
+        if let State::OpenChannelRequestConfirmed { .. } = guard.state {
+            guard.state = State::ChannelClosed;
 
             Self::wakeup(guard);
+        }
+    }
 
-            Ok(())
-        } else {
-            Err(Error::UnexpectedChannelState {
-                expected_state: &"OpenChannelRequestConfirmed",
-                actual_state: (&guard.state).into(),
-            })
+    /// Same as `set_channel_closed`, except that `wait_for_process_exit`
+    /// would return `Err(ChannelAborted)`.
+    pub(crate) fn set_channel_aborted(&self) {
+        let mut guard = self.0.lock().unwrap();
+
+        if let State::OpenChannelRequestConfirmed { .. } = guard.state {
+            guard.state = State::ChannelAborted;
+
+            Self::wakeup(guard);
         }
     }
 
@@ -1,17 +1,26 @@
 use std::{
+    convert::TryInto,
+    num::NonZeroU32,
     ops::Deref,
-    sync::{atomic::AtomicU8, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed},
+        Arc, Mutex,
+    },
 };
 
 use bytes::BytesMut;
+use integer_hasher::IntMap;
+use serde::Serialize;
+use tokio::sync::OwnedSemaphorePermit;
 
 use super::{ChannelDataArenaArc, SharedData};
-use crate::{request::ChannelClose, Error};
+use crate::{
+    request::{ChannelAdjustWindow, ChannelClose, OpenChannel, Request},
+    Error,
+};
 
 mod channel_state;
-pub(super) use channel_state::{
-    ChannelState, OpenChannelRequestedInner, OpenChannelRes, ProcessStatus,
-};
+pub(super) use channel_state::{ChannelAborted, ChannelState, OpenChannelRes};
 
 mod mpsc_bytes_channel;
 pub(super) use mpsc_bytes_channel::MpscBytesChannel;
@@ -28,6 +37,12 @@ pub use channel_input::ChannelInput;
 mod channel_output;
 pub use channel_output::ChannelOutput;
 
+mod channel_config;
+pub use channel_config::ChannelConfig;
+
+mod receiver_window;
+pub(super) use receiver_window::ReceiverWindow;
+
 #[derive(Debug)]
 // Use C repr so that we can decide order of fields here
 // and avoid false sharing if possible.
@@ -37,10 +52,9 @@ pub(super) struct ChannelData {
 
     pub(super) pending_requests: PendingRequests,
 
-    /// Number of receivers alive.
-    /// Max value is 2, since there can only be rx (stdout)
-    /// and stderr.
-    pub(super) receivers_count: AtomicU8,
+    /// Number of receivers alive, including rx (stdout), stderr
+    /// and extended data registered.
+    pub(super) receivers_count: AtomicUsize,
 
     /// Usually stdout for process or rx for forwarding.
     ///
@@ -60,8 +74,138 @@ pub(super) struct ChannelData {
     /// can receive bytes out of it without `unwrap`.
     pub(super) stderr: Option<Arc<MpscBytesChannel>>,
 
+    /// Extended data other than stderr, indexed by data type code.
+    ///
+    /// Set to `None` once eof is received.
+    pub(super) extended_data: Mutex<Option<IntMap<u32, Arc<MpscBytesChannel>>>>,
+
     /// Use u64 to avoid overflow.
     pub(super) sender_window_size: AwaitableAtomicU64,
+
+    /// Set once `eow@openssh.com` is received, after which
+    /// sshd would discard any data sent to the channel.
+    pub(super) eow_received: AtomicBool,
+
+    /// Set once the close packet is sent, after which no more
+    /// packet can be sent to the channel.
+    pub(super) close_sent: AtomicBool,
+
+    /// Extended as the data received is consumed by the receivers,
+    /// or discarded if there is no receiver for it.
+    pub(super) receiver_window: Mutex<ReceiverWindow>,
+
+    /// Held until the channel data is dropped if the number of
+    /// concurrent channels is limited.
+    pub(super) channel_permit: Option<OwnedSemaphorePermit>,
+}
+
+impl ChannelData {
+    /// * `has_stderr` - `true` if the channel has extended data stream
+    ///   stderr, e.g. session channel.
+    pub(super) fn new(has_stderr: bool, config: &ChannelConfig) -> Self {
+        Self {
+            state: ChannelState::new(),
+            pending_requests: PendingRequests::default(),
+            receivers_count: AtomicUsize::new(1 + usize::from(has_stderr)),
+            rx: Some(Arc::default()),
+            stderr: has_stderr.then(Arc::default),
+            extended_data: Mutex::new(Some(IntMap::default())),
+            sender_window_size: AwaitableAtomicU64::default(),
+            eow_received: AtomicBool::new(false),
+            close_sent: AtomicBool::new(false),
+            receiver_window: Mutex::new(ReceiverWindow::new(config)),
+            channel_permit: None,
+        }
+    }
+
+    /// Record that `n` bytes received are consumed or discarded.
+    ///
+    /// Returns the number of bytes to extend the window by, if
+    /// it should be extended and there are still active receivers.
+    pub(super) fn release_receiver_window(&self, n: usize) -> Option<u32> {
+        if n == 0 {
+            return None;
+        }
+
+        let n = n.try_into().unwrap_or(u32::MAX);
+        let bytes_to_add = self.receiver_window.lock().unwrap().consume(n)?;
+
+        (self.receivers_count.load(Relaxed) != 0).then_some(bytes_to_add)
+    }
+
+    /// Send close packet to `recipient_channel` unless it is
+    /// already sent.
+    pub(super) fn send_close(&self, recipient_channel: u32, shared_data: &SharedData) {
+        if self.close_sent.swap(true, Relaxed) {
+            return;
+        }
+
+        // The window is useless once the channel is closed.
+        self.receiver_window.lock().unwrap().close();
+
+        // Wake up `ChannelInput` waiting for window size
+        // so that it would return error.
+        self.sender_window_size.add(0);
+
+        // The close packet is 10 bytes large
+        let mut buffer = BytesMut::with_capacity(10);
+
+        ChannelClose::new(recipient_channel)
+            .serialize_with_header(&mut buffer, 0)
+            .expect("Serialization should not fail here");
+
+        shared_data.get_write_channel().push_bytes(buffer.freeze());
+    }
+}
+
+impl ChannelData {
+    /// Register a receiver of extended data `data_type`.
+    ///
+    /// Returns `None` if `data_type` is already registered.
+    pub(super) fn register_extended_data(&self, data_type: u32) -> Option<Arc<MpscBytesChannel>> {
+        let channel = Arc::<MpscBytesChannel>::default();
+
+        let mut guard = self.extended_data.lock().unwrap();
+
+        match &mut *guard {
+            Some(map) => {
+                if map.contains_key(&data_type) {
+                    return None;
+                }
+                map.insert(data_type, channel.clone());
+            }
+            // Eof is already received
+            None => channel.mark_eof(),
+        }
+
+        self.receivers_count.fetch_add(1, Relaxed);
+
+        Some(channel)
+    }
+
+    /// Return the receiver of extended data `data_type`, if registered.
+    pub(super) fn get_extended_data(&self, data_type: u32) -> Option<Arc<MpscBytesChannel>> {
+        self.extended_data
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|map| map.get(&data_type).cloned())
+    }
+
+    /// Mark all receivers of extended data as eof.
+    ///
+    /// * `is_aborted` - `true` if the connection is broken.
+    pub(super) fn mark_extended_data_eof(&self, is_aborted: bool) {
+        let map = self.extended_data.lock().unwrap().take();
+
+        for channel in map.into_iter().flat_map(IntMap::into_values) {
+            if is_aborted {
+                channel.mark_aborted();
+            } else {
+                channel.mark_eof();
+            }
+        }
+    }
 }
 
 /// Reference to the channel.
@@ -74,26 +218,132 @@ pub(super) struct ChannelData {
 /// the ChannelDataArenaArc.
 /// Afterwards, it would be the read_task's responsibility.
 #[derive(Clone, Debug)]
-struct ChannelRef(Arc<ChannelRefInner>);
+pub(super) struct ChannelRef(Arc<ChannelRefInner>);
+
+impl ChannelRef {
+    /// Open a new channel and wait for its confirmation.
+    ///
+    /// * `has_stderr` - `true` if the channel has extended data stream
+    ///   stderr, e.g. session channel.
+    /// * `config` - flow control configuration of the channel.
+    /// * `create_request` - Create the open channel request from
+    ///   `(sender_channel, initial_windows_size, max_packet_size)`.
+    ///
+    /// Returns the `ChannelRef` and the max packet size sshd accepts.
+    ///
+    /// If the max number of concurrent channels is reached, it waits
+    /// for other channels to be closed before sending the request.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe.
+    pub(super) async fn open<F, T>(
+        shared_data: &SharedData,
+        has_stderr: bool,
+        config: &ChannelConfig,
+        create_request: F,
+    ) -> Result<(Self, NonZeroU32), Error>
+    where
+        F: FnOnce(u32, u32, u32) -> Request<OpenChannel<T>>,
+        T: Serialize,
+    {
+        let channel_data = shared_data
+            .insert_channel_data(ChannelData::new(has_stderr, config))
+            .await;
+        let channel_id = ChannelDataArenaArc::slot(&channel_data);
+
+        let mut buffer = BytesMut::new();
+
+        if let Err(err) = create_request(
+            channel_id,
+            config.get_window_size(),
+            config.get_max_packet_size(),
+        )
+        .serialize_with_header(&mut buffer, 0)
+        {
+            shared_data.remove_channel_data(channel_id)?;
+            return Err(err);
+        }
+
+        shared_data.get_write_channel().push_bytes(buffer.freeze());
+
+        let res = tokio::select! {
+            biased;
+
+            res = channel_data.state.wait_for_confirmation() => res,
+
+            // The read task has exited, nobody would confirm it.
+            _ = shared_data.get_cancellation_token().cancelled() => {
+                shared_data.remove_channel_data(channel_id)?;
+
+                return Err(shared_data.get_terminal_error());
+            }
+        };
+
+        match res {
+            OpenChannelRes::Confirmed {
+                recipient_channel,
+                max_packet_size,
+            } => {
+                let channel_ref = Self::new(shared_data.clone(), channel_data, recipient_channel);
+
+                let max_packet_size = NonZeroU32::new(max_packet_size)
+                    .ok_or(Error::InvalidResponse(&"max_packet_size is 0"))?;
+
+                Ok((channel_ref, max_packet_size))
+            }
+            OpenChannelRes::Failed(failure) => {
+                shared_data.remove_channel_data(channel_id)?;
+
+                Err(failure.into())
+            }
+        }
+    }
+}
+
+impl ChannelRef {
+    /// Create `ChannelRef` for channel opened by sshd, which is confirmed
+    /// by the read task.
+    pub(super) fn new(
+        shared_data: SharedData,
+        channel_data: ChannelDataArenaArc,
+        recipient_channel: u32,
+    ) -> Self {
+        Self(Arc::new(ChannelRefInner {
+            shared_data,
+            channel_data,
+            recipient_channel,
+        }))
+    }
+}
 
 #[derive(Debug)]
-struct ChannelRefInner {
-    shared_data: SharedData,
-    channel_data: ChannelDataArenaArc,
+pub(super) struct ChannelRefInner {
+    pub(super) shared_data: SharedData,
+    pub(super) channel_data: ChannelDataArenaArc,
+
+    /// Channel id allocated by sshd, which is used as the recipient channel
+    /// for all packets sent to this channel.
+    recipient_channel: u32,
 }
 
 impl ChannelRefInner {
-    fn channel_id(&self) -> u32 {
-        ChannelDataArenaArc::slot(&self.channel_data)
+    pub(super) fn recipient_channel(&self) -> u32 {
+        self.recipient_channel
     }
 
-    fn send_close(&mut self) {
-        let channel_id = self.channel_id();
+    /// Record that `n` bytes received are consumed and send
+    /// window adjustment to sshd if necessary.
+    pub(super) fn release_receiver_window(&self, n: usize) {
+        let bytes_to_add = match self.channel_data.release_receiver_window(n) {
+            Some(bytes_to_add) => bytes_to_add,
+            None => return,
+        };
 
-        // The close packet is 10 bytes large
-        let mut buffer = BytesMut::with_capacity(10);
+        // The adjust window packet is 14 bytes large
+        let mut buffer = BytesMut::with_capacity(14);
 
-        ChannelClose::new(channel_id)
+        ChannelAdjustWindow::new(self.recipient_channel(), bytes_to_add)
             .serialize_with_header(&mut buffer, 0)
             .expect("Serialization should not fail here");
 
@@ -101,10 +351,19 @@ impl ChannelRefInner {
             .get_write_channel()
             .push_bytes(buffer.freeze());
     }
+
+    /// Channel id allocated by us, which is reported by
+    /// [`ProxyClient::close_with`](super::ProxyClient::close_with)
+    /// if the channel is aborted.
+    pub(super) fn channel_id(&self) -> u32 {
+        ChannelDataArenaArc::slot(&self.channel_data)
+    }
 }
+
 impl Drop for ChannelRefInner {
     fn drop(&mut self) {
-        self.send_close();
+        self.channel_data
+            .send_close(self.recipient_channel, &self.shared_data);
     }
 }
 
@@ -115,3 +374,75 @@ impl Deref for ChannelRef {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        fake_sshd::{connect, exec},
+        ProxyClient,
+    };
+    use super::*;
+
+    use std::io;
+
+    use futures_util::StreamExt;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        task::yield_now,
+    };
+
+    fn assert_connection_broken(err: Error) {
+        assert_matches!(
+            err,
+            Error::ConnectionBroken(cause) if matches!(
+                &*cause,
+                Error::IOError(err) if err.kind() == io::ErrorKind::UnexpectedEof
+            )
+        );
+    }
+
+    fn assert_io_connection_broken(err: io::Error) {
+        assert_connection_broken(*err.into_inner().unwrap().downcast::<Error>().unwrap());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_connection_broken() {
+        let (client, mut sshd) = connect(&ProxyClient::builder());
+
+        // Window of 0, so that writing to stdin blocks.
+        let (mut child, _) = exec(&client, &mut sshd, 0, 0).await;
+
+        let stdin = child.stdin.take().unwrap();
+        let mut stdout = child.stdout.take().unwrap();
+        let mut stderr = child.stderr.take().unwrap();
+
+        let write_stdin = async {
+            tokio::pin!(stdin);
+
+            // Data is buffered until there is window to send it.
+            stdin.write_all(b"data").await.unwrap();
+            stdin.flush().await.unwrap_err()
+        };
+        let read_stdout = async { stdout.read_to_end(&mut Vec::new()).await.unwrap_err() };
+        let read_stderr = async {
+            assert_eq!(stderr.next().await, None);
+            stderr.connection_error().unwrap()
+        };
+        let break_connection = async {
+            // Let all of the above block first.
+            yield_now().await;
+            drop(sshd);
+        };
+
+        let (stdin_err, stdout_err, stderr_err, ()) =
+            tokio::join!(write_stdin, read_stdout, read_stderr, break_connection);
+
+        assert_io_connection_broken(stdin_err);
+        assert_io_connection_broken(stdout_err);
+        assert_connection_broken(stderr_err);
+        assert_connection_broken(child.wait().await.unwrap_err());
+
+        // The cause is still held by the channel above.
+        assert_connection_broken(client.close().await.unwrap_err());
+    }
+}
@@ -21,6 +21,9 @@ struct Inner {
     /// Set to true if the reader is dropped so that
     /// no new data will be added.
     reader_dropped: bool,
+
+    /// Set to true if eof is caused by broken connection.
+    is_aborted: bool,
 }
 
 /// Methods for the read end
@@ -50,7 +53,7 @@ impl MpscBytesChannel {
             return Poll::Ready(());
         }
 
-        let prev_waker = mem::replace(&mut guard.waker, Some(cx.waker().clone()));
+        let prev_waker = guard.waker.replace(cx.waker().clone());
 
         // Release the lock
         drop(guard);
@@ -73,9 +76,23 @@ impl MpscBytesChannel {
         poll_fn(move |cx| self.poll_for_data(alt_buffer, cx))
     }
 
+    /// Return `true` if eof is caused by broken connection
+    /// instead of sshd.
+    pub(crate) fn is_aborted(&self) -> bool {
+        self.0.lock().unwrap().is_aborted
+    }
+
+    /// Number of bytes buffered and not yet polled by the reader.
+    #[cfg(test)]
+    pub(crate) fn buffered_len(&self) -> usize {
+        self.0.lock().unwrap().buffer.iter().map(Bytes::len).sum()
+    }
+
     /// Drop the reader.
     /// After this point, you cannot call poll_for_data.
-    pub(crate) fn drop_reader(&self) {
+    ///
+    /// Returns number of bytes buffered and dropped.
+    pub(crate) fn drop_reader(&self) -> usize {
         let mut guard = self.0.lock().unwrap();
 
         let prev_waker = mem::take(&mut guard.waker);
@@ -88,27 +105,33 @@ impl MpscBytesChannel {
 
         // Drop the waker/buffer here to reduce the critical section
         drop(prev_waker);
-        drop(prev_buffer);
+
+        prev_buffer.iter().map(Bytes::len).sum()
     }
 }
 
 /// Methods for the write end
 impl MpscBytesChannel {
-    pub(crate) fn push_bytes(&self, data: Bytes) {
+    /// Returns `false` if the reader is dropped and `data` is discarded.
+    pub(crate) fn push_bytes(&self, data: Bytes) -> bool {
         if !data.is_empty() {
             self.add_more_data(1, Some(data))
+        } else {
+            true
         }
     }
 
     /// * `n` - number of space to reserve before extending using iter.
-    pub(crate) fn add_more_data<It>(&self, n: usize, iter: It)
+    ///
+    /// Returns `false` if the reader is dropped and `iter` is discarded.
+    pub(crate) fn add_more_data<It>(&self, n: usize, iter: It) -> bool
     where
         It: IntoIterator<Item = Bytes>,
     {
         let mut guard = self.0.lock().unwrap();
 
         if guard.reader_dropped {
-            return;
+            return false;
         }
 
         let buffer = &mut guard.buffer;
@@ -123,6 +146,8 @@ impl MpscBytesChannel {
         if after > before {
             Self::wake_up_reader(guard);
         }
+
+        true
     }
 
     /// You must not call add_more_data after this call.
@@ -137,6 +162,25 @@ impl MpscBytesChannel {
         Self::wake_up_reader(guard);
     }
 
+    /// Return `true` if `mark_eof` or `mark_aborted` has been called.
+    pub(crate) fn is_eof(&self) -> bool {
+        self.0.lock().unwrap().is_eof
+    }
+
+    /// Same as `mark_eof`, except that the reader would see
+    /// the connection as broken.
+    pub(crate) fn mark_aborted(&self) {
+        let mut guard = self.0.lock().unwrap();
+
+        if guard.reader_dropped {
+            return;
+        }
+
+        guard.is_eof = true;
+        guard.is_aborted = true;
+        Self::wake_up_reader(guard);
+    }
+
     fn wake_up_reader(mut guard: MutexGuard<'_, Inner>) {
         let waker = guard.waker.take();
 
@@ -42,7 +42,7 @@ impl PendingRequests {
     /// are flushed.
     ///
     /// Once start_new_requests, wait_for_completion must be called.
-    pub(crate) async fn start_new_requests<F>(&self, requests: NonZeroUsize) {
+    pub(crate) async fn start_new_requests(&self, requests: NonZeroUsize) {
         struct WaitForPrevCompletion<'a>(&'a PendingRequests);
 
         impl<'a> Future for WaitForPrevCompletion<'a> {
@@ -54,7 +54,7 @@ impl PendingRequests {
                 match &mut *guard {
                     Inner::Done(..) | Inner::NotStarted => Poll::Ready(guard),
                     Inner::Waiting { waker, .. } => {
-                        let prev_waker = mem::replace(waker, Some(cx.waker().clone()));
+                        let prev_waker = waker.replace(cx.waker().clone());
 
                         // Release mutex
                         drop(guard);
@@ -96,7 +96,7 @@ impl PendingRequests {
                 match &mut *guard {
                     Inner::Done(completion) => Poll::Ready(*completion),
                     Inner::Waiting { waker, .. } => {
-                        let prev_waker = mem::replace(waker, Some(cx.waker().clone()));
+                        let prev_waker = waker.replace(cx.waker().clone());
 
                         // Release mutex
                         drop(guard);
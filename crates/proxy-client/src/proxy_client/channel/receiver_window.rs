@@ -0,0 +1,79 @@
+use std::time::{Duration, Instant};
+
+use super::ChannelConfig;
+
+/// If half of the window is consumed within this duration, then
+/// the window is considered to be limiting the throughput.
+const ADAPTIVE_WINDOW_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Tracks how many bytes of the window are not yet consumed by
+/// the receivers and decides when and by how much the window
+/// should be extended.
+///
+/// Since the window is only extended once data is consumed, the
+/// number of bytes buffered for a channel is bounded by the window.
+#[derive(Clone, Debug)]
+pub(crate) struct ReceiverWindow {
+    /// Current target size of the window.
+    window_size: u32,
+    /// Window would not grow beyond it.
+    max_window_size: u32,
+
+    /// Number of bytes sshd can send without waiting, minus the
+    /// bytes received but not yet consumed.
+    remaining: u32,
+
+    /// When the window is last extended.
+    last_extended: Instant,
+
+    /// Set once eof is received, after which sshd would not send
+    /// any data, so the window should not be extended anymore.
+    is_closed: bool,
+}
+
+impl ReceiverWindow {
+    pub(crate) fn new(config: &ChannelConfig) -> Self {
+        let window_size = config.get_window_size();
+
+        Self {
+            window_size,
+            max_window_size: config.get_max_window_size(),
+            remaining: window_size,
+            last_extended: Instant::now(),
+            is_closed: false,
+        }
+    }
+
+    /// Stop extending the window.
+    pub(crate) fn close(&mut self) {
+        self.is_closed = true;
+    }
+
+    /// Record that `n` bytes of the window are consumed.
+    ///
+    /// Returns the number of bytes to extend the window by once
+    /// more than half of it is consumed, so that sshd can keep sending
+    /// while the adjustment is in flight.
+    pub(crate) fn consume(&mut self, n: u32) -> Option<u32> {
+        self.remaining = self.remaining.saturating_sub(n);
+
+        if self.is_closed || self.remaining > self.window_size / 2 {
+            return None;
+        }
+
+        let now = Instant::now();
+
+        if self.window_size < self.max_window_size
+            && now.duration_since(self.last_extended) < ADAPTIVE_WINDOW_THRESHOLD
+        {
+            self.window_size = self.window_size.saturating_mul(2).min(self.max_window_size);
+        }
+
+        self.last_extended = now;
+
+        let bytes_to_add = self.window_size - self.remaining;
+        self.remaining = self.window_size;
+
+        Some(bytes_to_add)
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    io,
+    num::NonZeroU32,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use pin_project::pin_project;
+use serde::Serialize;
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf};
+
+use super::{
+    channel::{ChannelConfig, ChannelInput, ChannelOutput, ChannelRef},
+    SharedData,
+};
+use crate::{
+    request::{OpenChannel, Request},
+    Error,
+};
+
+/// Bidirectional stream over a data channel, e.g. `direct-tcpip`.
+///
+/// The channel is closed once `ChannelStream` is dropped, or once both
+/// halves returned by [`ChannelStream::into_split`] are dropped.
+#[derive(Debug)]
+#[pin_project]
+pub struct ChannelStream {
+    #[pin]
+    input: ChannelInput,
+    output: ChannelOutput,
+}
+
+impl ChannelStream {
+    /// Open a new data channel and wait for its confirmation.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe.
+    pub(super) async fn open<F, T>(
+        shared_data: &SharedData,
+        config: &ChannelConfig,
+        create_request: F,
+    ) -> Result<Self, Error>
+    where
+        F: FnOnce(u32, u32, u32) -> Request<OpenChannel<T>>,
+        T: Serialize,
+    {
+        let (channel_ref, max_packet_size) =
+            ChannelRef::open(shared_data, false, config, create_request).await?;
+
+        Ok(Self::new(channel_ref, max_packet_size))
+    }
+
+    pub(super) fn new(channel_ref: ChannelRef, max_packet_size: NonZeroU32) -> Self {
+        let rx = channel_ref
+            .channel_data
+            .rx
+            .clone()
+            .expect("Data channel must have rx");
+
+        Self {
+            output: ChannelOutput::new(channel_ref.clone(), rx),
+            input: ChannelInput::new(channel_ref, max_packet_size),
+        }
+    }
+
+    /// Id of the channel, same as the one returned by the halves
+    /// created by [`ChannelStream::into_split`].
+    pub fn channel_id(&self) -> u32 {
+        self.output.channel_id()
+    }
+
+    /// Split the stream so that it can be read and written concurrently.
+    ///
+    /// Dropping [`ChannelInput`] sends eof to the remote.
+    pub fn into_split(self) -> (ChannelInput, ChannelOutput) {
+        (self.input, self.output)
+    }
+}
+
+impl AsyncRead for ChannelStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(self.project().output).poll_read(cx, buf)
+    }
+}
+
+impl AsyncBufRead for ChannelStream {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(self.project().output).poll_fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        Pin::new(self.project().output).consume(amt)
+    }
+}
+
+impl AsyncWrite for ChannelStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.project().input.poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.project().input.poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.input.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().input.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().input.poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,216 @@
+//! Just enough of sshd over [`tokio::io::duplex`] to drive
+//! [`ProxyClient`] in unit tests.
+
+use std::{borrow::Cow, convert::TryInto};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::io::{duplex, split, AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+use super::{ChannelConfig, Child, ProxyClient, ProxyClientBuilder, Session};
+use crate::constants::*;
+
+/// Create a [`ProxyClient`] connected to a [`FakeSshd`].
+pub(super) fn connect(builder: &ProxyClientBuilder) -> (ProxyClient, FakeSshd) {
+    connect_with_buffer_size(builder, 1024 * 1024)
+}
+
+/// Same as [`connect`], except that at most `buffer_size` bytes could
+/// be buffered in each direction.
+pub(super) fn connect_with_buffer_size(
+    builder: &ProxyClientBuilder,
+    buffer_size: usize,
+) -> (ProxyClient, FakeSshd) {
+    let (client, server) = duplex(buffer_size);
+    let (rx, tx) = split(client);
+
+    (builder.build(rx, tx), FakeSshd(server))
+}
+
+/// Append `bytes` encoded as ssh `string`.
+pub(super) fn put_string(buffer: &mut BytesMut, bytes: &[u8]) {
+    buffer.put_u32(bytes.len().try_into().unwrap());
+    buffer.put_slice(bytes);
+}
+
+#[derive(Debug)]
+pub(super) struct FakeSshd(DuplexStream);
+
+impl FakeSshd {
+    /// Read one packet and return its type and payload.
+    pub(super) async fn read_packet(&mut self) -> (u8, Bytes) {
+        let packet_len = self.0.read_u32().await.unwrap();
+
+        let mut packet = BytesMut::new();
+        packet.resize(packet_len.try_into().unwrap(), 0);
+        self.0.read_exact(&mut packet).await.unwrap();
+
+        let mut packet = packet.freeze();
+        let (padding_len, packet_type) = (packet[0], packet[1]);
+        assert_eq!(padding_len, 0);
+
+        (packet_type, packet.split_off(2))
+    }
+
+    /// Read packets until a packet of `packet_type` is received and
+    /// return its payload, packets of any other type are discarded.
+    pub(super) async fn expect_packet(&mut self, packet_type: u8) -> Bytes {
+        loop {
+            let (received_type, payload) = self.read_packet().await;
+            if received_type == packet_type {
+                break payload;
+            }
+        }
+    }
+
+    /// Assert that the client shuts down the connection without
+    /// sending anything else.
+    pub(super) async fn expect_eof(&mut self) {
+        let mut buffer = Vec::new();
+        self.0.read_to_end(&mut buffer).await.unwrap();
+        assert_eq!(buffer, []);
+    }
+
+    pub(super) async fn write_packet(&mut self, packet_type: u8, payload: &[u8]) {
+        let packet_len: u32 = (payload.len() + 2).try_into().unwrap();
+
+        let mut packet = BytesMut::new();
+        packet.put_u32(packet_len);
+        packet.put_u8(0);
+        packet.put_u8(packet_type);
+        packet.put_slice(payload);
+
+        self.0.write_all(&packet).await.unwrap();
+    }
+
+    /// Write a packet of `packet_type` sent to channel `recipient_channel`.
+    pub(super) async fn write_channel_packet(
+        &mut self,
+        packet_type: u8,
+        recipient_channel: u32,
+        payload: &[u8],
+    ) {
+        let mut buffer = BytesMut::new();
+        buffer.put_u32(recipient_channel);
+        buffer.put_slice(payload);
+
+        self.write_packet(packet_type, &buffer).await
+    }
+
+    /// Send `data` to channel `recipient_channel`.
+    pub(super) async fn write_data(&mut self, recipient_channel: u32, data: &[u8]) {
+        let mut buffer = BytesMut::new();
+        put_string(&mut buffer, data);
+
+        self.write_channel_packet(SSH_MSG_CHANNEL_DATA, recipient_channel, &buffer)
+            .await
+    }
+
+    /// Confirm the next channel open request with channel id
+    /// `sender_channel` allocated by sshd and window `init_win_size`.
+    ///
+    /// Returns the channel id allocated by the client and its initial
+    /// window size.
+    pub(super) async fn accept_channel(
+        &mut self,
+        sender_channel: u32,
+        init_win_size: u32,
+    ) -> (u32, u32) {
+        let payload = self.expect_packet(SSH_MSG_CHANNEL_OPEN).await;
+
+        let (_channel_type, recipient_channel, client_win_size, _max_packet_size): (
+            Cow<'_, str>,
+            u32,
+            u32,
+            u32,
+        ) = ssh_format::from_bytes(&payload).unwrap().0;
+
+        let mut buffer = BytesMut::new();
+        buffer.put_u32(sender_channel);
+        buffer.put_u32(init_win_size);
+        buffer.put_u32(32 * 1024);
+
+        self.write_channel_packet(
+            SSH_MSG_CHANNEL_OPEN_CONFIRMATION,
+            recipient_channel,
+            &buffer,
+        )
+        .await;
+
+        (recipient_channel, client_win_size)
+    }
+
+    /// Reply success to the next channel request, whose recipient
+    /// must be `sender_channel`.
+    ///
+    /// Returns the type of the request.
+    pub(super) async fn accept_channel_request(
+        &mut self,
+        sender_channel: u32,
+        recipient_channel: u32,
+    ) -> String {
+        let payload = self.expect_packet(SSH_MSG_CHANNEL_REQUEST).await;
+
+        let (channel, request_type): (u32, String) = ssh_format::from_bytes(&payload).unwrap().0;
+        assert_eq!(channel, sender_channel);
+
+        self.write_channel_packet(SSH_MSG_CHANNEL_SUCCESS, recipient_channel, &[])
+            .await;
+
+        request_type
+    }
+}
+
+/// Open a session on `client` configured by `config`, which is accepted
+/// by `sshd` as channel `sender_channel` with window `init_win_size`.
+///
+/// Returns the session and the channel id allocated by the client.
+pub(super) async fn open_session(
+    client: &ProxyClient,
+    sshd: &mut FakeSshd,
+    sender_channel: u32,
+    init_win_size: u32,
+    config: &ChannelConfig,
+) -> (Session, u32) {
+    let (session, (recipient_channel, _)) = tokio::join!(
+        client.open_session_with_config(config),
+        sshd.accept_channel(sender_channel, init_win_size),
+    );
+
+    (session.unwrap(), recipient_channel)
+}
+
+/// Exec a command in `session`, whose channel ids are `sender_channel`
+/// and `recipient_channel` as returned by [`open_session`].
+pub(super) async fn exec_session(
+    session: Session,
+    sshd: &mut FakeSshd,
+    sender_channel: u32,
+    recipient_channel: u32,
+) -> Child {
+    let (child, request_type) = tokio::join!(
+        session.exec(Cow::Borrowed("true".try_into().unwrap())),
+        sshd.accept_channel_request(sender_channel, recipient_channel),
+    );
+    assert_eq!(request_type, "exec");
+
+    child.unwrap()
+}
+
+/// Same as [`open_session`] followed by [`exec_session`], using the
+/// default channel config of `client`.
+///
+/// Returns the child and the channel id allocated by the client.
+pub(super) async fn exec(
+    client: &ProxyClient,
+    sshd: &mut FakeSshd,
+    sender_channel: u32,
+    init_win_size: u32,
+) -> (Child, u32) {
+    let config = client.shared_data.get_channel_config();
+
+    let (session, recipient_channel) =
+        open_session(client, sshd, sender_channel, init_win_size, config).await;
+    let child = exec_session(session, sshd, sender_channel, recipient_channel).await;
+
+    (child, recipient_channel)
+}
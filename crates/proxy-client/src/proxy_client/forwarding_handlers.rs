@@ -0,0 +1,59 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use compact_str::CompactString;
+
+use super::ChannelStream;
+
+/// Channel opened by sshd for X11 forwarding.
+#[derive(Debug)]
+pub struct ForwardedX11 {
+    pub stream: ChannelStream,
+
+    /// Address of the X11 client connected on remote.
+    pub originator_address: CompactString,
+    pub originator_port: u32,
+}
+
+type AgentHandler = Arc<dyn Fn(ChannelStream) + Send + Sync>;
+type X11Handler = Arc<dyn Fn(ForwardedX11) + Send + Sync>;
+
+/// Handlers of `auth-agent@openssh.com` and `x11` channels opened by sshd.
+#[derive(Default)]
+pub(super) struct ForwardingHandlers {
+    agent: Mutex<Option<AgentHandler>>,
+    x11: Mutex<Option<X11Handler>>,
+}
+
+impl fmt::Debug for ForwardingHandlers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ForwardingHandlers")
+            .field("agent", &self.agent.lock().unwrap().is_some())
+            .field("x11", &self.x11.lock().unwrap().is_some())
+            .finish()
+    }
+}
+
+impl ForwardingHandlers {
+    pub(super) fn set_agent(&self, handler: AgentHandler) {
+        *self.agent.lock().unwrap() = Some(handler);
+    }
+
+    pub(super) fn set_x11(&self, handler: X11Handler) {
+        *self.x11.lock().unwrap() = Some(handler);
+    }
+
+    /// Cloned out of the lock so that the handler can be called without
+    /// holding it.
+    pub(super) fn get_agent(&self) -> Option<AgentHandler> {
+        self.agent.lock().unwrap().clone()
+    }
+
+    /// Cloned out of the lock so that the handler can be called without
+    /// holding it.
+    pub(super) fn get_x11(&self) -> Option<X11Handler> {
+        self.x11.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,141 @@
+use std::{collections::VecDeque, fmt, sync::Mutex};
+
+use bytes::{Bytes, BytesMut};
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use super::SharedData;
+use crate::{
+    request::{GlobalRequest, Request},
+    Error,
+};
+
+/// Called by the read task with the response specific data on success,
+/// or `None` on failure.
+type Callback = Box<dyn FnOnce(Option<Bytes>) + Send>;
+
+/// Global requests waiting for reply.
+///
+/// sshd replies to global requests in the order they are sent, so
+/// the callbacks are stored in a FIFO queue.
+#[derive(Default)]
+pub(super) struct GlobalRequests(Mutex<VecDeque<Callback>>);
+
+impl fmt::Debug for GlobalRequests {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("GlobalRequests")
+            .field(&self.0.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl GlobalRequests {
+    /// Called by the read task on receiving the reply.
+    pub(super) fn report_reply(&self, reply: Option<Bytes>) -> Result<(), Error> {
+        let callback = self
+            .0
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or(Error::UnexpectedRequestResponse)?;
+
+        callback(reply);
+
+        Ok(())
+    }
+
+    /// Drop all callbacks, called by the read task on exit after the
+    /// cancellation token is cancelled, so that no callback could be
+    /// pushed afterwards.
+    pub(super) fn clear(&self) {
+        let callbacks = std::mem::take(&mut *self.0.lock().unwrap());
+
+        // Drop the callbacks outside of the critical section.
+        drop(callbacks);
+    }
+}
+
+impl SharedData {
+    /// Send a global request which wants reply, `callback` is called
+    /// in the read task once the reply is received.
+    pub(super) fn send_global_request_with_callback<T: Serialize>(
+        &self,
+        request: Request<GlobalRequest<T>>,
+        callback: Callback,
+    ) -> Result<(), Error> {
+        let mut buffer = BytesMut::new();
+        request.serialize_with_header(&mut buffer, 0)?;
+
+        // Hold the lock while pushing the request so that
+        // the order of callbacks matches the order of requests.
+        let mut guard = self.get_global_requests().0.lock().unwrap();
+
+        // The read task has exited and cleared the callbacks,
+        // nobody would call `callback`.
+        if self.get_cancellation_token().is_cancelled() {
+            return Err(self.get_terminal_error());
+        }
+
+        guard.push_back(callback);
+        self.get_write_channel().push_bytes(buffer.freeze());
+
+        Ok(())
+    }
+
+    /// Send a global request which wants reply and wait for the result
+    /// of `callback`, which is called in the read task once the reply
+    /// is received.
+    ///
+    /// If the returned future is dropped before that, the result of
+    /// `callback` is dropped in the read task.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe.
+    pub(super) async fn send_global_request_and_wait<T, R, F>(
+        &self,
+        request: Request<GlobalRequest<T>>,
+        callback: F,
+    ) -> Result<R, Error>
+    where
+        T: Serialize,
+        R: Send + 'static,
+        F: FnOnce(Option<Bytes>) -> Result<R, Error> + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+
+        self.send_global_request_with_callback(
+            request,
+            Box::new(move |reply| {
+                // Nobody is waiting for the result if the request is cancelled.
+                let _ = sender.send(callback(reply));
+            }),
+        )?;
+
+        tokio::select! {
+            biased;
+
+            res = receiver => match res {
+                Ok(res) => res,
+                // The callback is dropped by the read task on exit.
+                Err(_) => Err(self.get_terminal_error()),
+            },
+
+            // The read task has exited, nobody would reply.
+            _ = self.get_cancellation_token().cancelled() => Err(self.get_terminal_error()),
+        }
+    }
+
+    /// Send a global request which does not want reply.
+    pub(super) fn send_global_request_no_reply<T: Serialize>(
+        &self,
+        request: Request<GlobalRequest<T>>,
+    ) -> Result<(), Error> {
+        let mut buffer = BytesMut::new();
+        request.serialize_with_header(&mut buffer, 0)?;
+
+        self.get_write_channel().push_bytes(buffer.freeze());
+
+        Ok(())
+    }
+}
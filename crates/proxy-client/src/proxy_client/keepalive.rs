@@ -0,0 +1,171 @@
+use std::{
+    future::pending,
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicU32, Ordering::Relaxed},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::time::{interval_at, Instant, Interval, MissedTickBehavior};
+
+use super::SharedData;
+use crate::{request::KeepaliveRequest, Error};
+
+/// Configuration of keepalive, set by
+/// [`ProxyClientBuilder::keepalive`](super::ProxyClientBuilder::keepalive).
+#[derive(Copy, Clone, Debug)]
+pub(super) struct KeepaliveConfig {
+    pub(super) interval: Duration,
+    pub(super) max_missed: NonZeroU32,
+}
+
+/// Sends `keepalive@openssh.com` periodically in the read task.
+#[derive(Debug)]
+pub(super) struct Keepalive {
+    interval: Interval,
+    max_missed: NonZeroU32,
+
+    /// Number of keepalive requests sent without any reply,
+    /// reset to 0 by the callback once a reply is received.
+    missed: Arc<AtomicU32>,
+}
+
+impl Keepalive {
+    pub(super) fn new(config: KeepaliveConfig) -> Self {
+        let mut interval = interval_at(Instant::now() + config.interval, config.interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        Self {
+            interval,
+            max_missed: config.max_missed,
+            missed: Arc::default(),
+        }
+    }
+
+    /// Wait until the next keepalive request is due and send it.
+    ///
+    /// Returns [`Error::KeepaliveTimeout`] if `max_missed` requests are
+    /// not replied.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is cancellation safe.
+    async fn tick(&mut self, shared_data: &SharedData) -> Result<(), Error> {
+        self.interval.tick().await;
+
+        let max_missed = self.max_missed.get();
+
+        if self.missed.load(Relaxed) >= max_missed {
+            return Err(Error::KeepaliveTimeout(max_missed));
+        }
+
+        self.missed.fetch_add(1, Relaxed);
+
+        let missed = self.missed.clone();
+
+        shared_data.send_global_request_with_callback(
+            KeepaliveRequest::new(),
+            Box::new(move |_reply| missed.store(0, Relaxed)),
+        )
+    }
+}
+
+/// Same as [`Keepalive::tick`], except that it never returns if
+/// keepalive is disabled.
+///
+/// # Cancel safety
+///
+/// This function is cancellation safe.
+pub(super) async fn keepalive_tick(
+    keepalive: Option<&mut Keepalive>,
+    shared_data: &SharedData,
+) -> Result<(), Error> {
+    match keepalive {
+        Some(keepalive) => keepalive.tick(shared_data).await,
+        None => pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        fake_sshd::{connect, exec, FakeSshd},
+        ProxyClient,
+    };
+    use super::*;
+    use crate::constants::*;
+
+    use std::borrow::Cow;
+
+    use tokio::{io::AsyncReadExt, select, time::sleep};
+
+    const INTERVAL: Duration = Duration::from_secs(1);
+
+    fn connect_with_keepalive(max_missed: u32) -> (ProxyClient, FakeSshd) {
+        connect(ProxyClient::builder().keepalive(INTERVAL, NonZeroU32::new(max_missed).unwrap()))
+    }
+
+    async fn expect_keepalive(sshd: &mut FakeSshd) {
+        let payload = sshd.expect_packet(SSH_MSG_GLOBAL_REQUEST).await;
+
+        let (request_name, want_reply): (Cow<'_, str>, u8) =
+            ssh_format::from_bytes(&payload).unwrap().0;
+        assert_eq!(request_name, "keepalive@openssh.com");
+        assert_eq!(want_reply, 1);
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn test_keepalive_timeout() {
+        let (client, mut sshd) = connect_with_keepalive(3);
+        let (mut child, _) = exec(&client, &mut sshd, 0, 1024).await;
+        let mut stdout = child.stdout.take().unwrap();
+
+        // sshd never replies, so the 4th tick finds 3 requests missed.
+        let start = Instant::now();
+        let err = stdout.read_to_end(&mut Vec::new()).await.unwrap_err();
+        assert_eq!(start.elapsed(), INTERVAL * 4);
+
+        let err = *err.into_inner().unwrap().downcast::<Error>().unwrap();
+        assert_matches!(err, Error::ConnectionBroken(cause) if matches!(*cause, Error::KeepaliveTimeout(3)));
+
+        for _ in 0..3 {
+            expect_keepalive(&mut sshd).await;
+        }
+
+        // The channel is aborted, so waiting on it does not hang.
+        assert_matches!(
+            child.wait().await,
+            Err(Error::ConnectionBroken(cause)) if matches!(*cause, Error::KeepaliveTimeout(3))
+        );
+        drop(stdout);
+
+        assert_matches!(client.close().await, Err(Error::KeepaliveTimeout(3)));
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn test_keepalive_replied() {
+        let (client, mut sshd) = connect_with_keepalive(1);
+        let (mut child, recipient_channel) = exec(&client, &mut sshd, 0, 1024).await;
+        let mut stdout = child.stdout.take().unwrap();
+
+        // Any reply counts, including failure.
+        let reply_keepalive = async {
+            loop {
+                expect_keepalive(&mut sshd).await;
+                sshd.write_packet(SSH_MSG_REQUEST_FAILURE, &[]).await;
+            }
+        };
+        select! {
+            _ = reply_keepalive => unreachable!(),
+            _ = sleep(INTERVAL * 10) => (),
+        }
+
+        sshd.write_data(recipient_channel, b"ok").await;
+
+        let mut buffer = [0_u8; 2];
+        stdout.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"ok");
+    }
+}
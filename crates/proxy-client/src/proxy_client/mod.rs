@@ -1,4 +1,7 @@
-use std::num::NonZeroUsize;
+use std::{
+    borrow::Cow, net::SocketAddr, num::NonZeroUsize, os::unix::ffi::OsStrExt, path::Path,
+    sync::Arc, time::Instant,
+};
 
 use openssh_proxy_client_error::Error;
 use tokio::{
@@ -7,6 +10,7 @@ use tokio::{
 };
 
 mod channel;
+pub use channel::{ChannelConfig, ChannelInput, ChannelOutput};
 
 mod shared_data;
 use shared_data::{ChannelDataArenaArc, SharedData};
@@ -17,36 +21,433 @@ use read_task::create_read_task;
 mod write_task;
 use write_task::create_write_task;
 
+mod session;
+pub use session::{Child, Session};
+
+mod channel_stream;
+pub use channel_stream::ChannelStream;
+
+mod global_requests;
+
+mod keepalive;
+
+mod builder;
+pub use builder::ProxyClientBuilder;
+
+mod forwarding_handlers;
+pub use forwarding_handlers::ForwardedX11;
+
+mod remote_forward;
+pub use remote_forward::{
+    ForwardedStreamLocal, ForwardedTcpIp, RemoteForwardListener, StreamLocalForwardListener,
+};
+
+#[cfg(test)]
+mod fake_sshd;
+
+use crate::request::{DirectStreamLocal, DirectTcpIp};
+
 #[derive(Debug)]
 pub struct ProxyClient {
     shared_data: SharedData,
-    read_task: JoinHandle<Result<(), Error>>,
-    write_task: JoinHandle<Result<(), Error>>,
+    read_task: JoinHandle<Result<Vec<u32>, Arc<Error>>>,
+    write_task: JoinHandle<Result<(), Arc<Error>>>,
 }
 
 impl ProxyClient {
     /// * `reusable_io_slice_cap` - determines how many `Bytes` can be sent
     ///   in one syscall to reduce overhead.
+    ///
+    /// Use [`ProxyClient::builder`] for more options.
     pub fn new<R, W>(rx: R, tx: W, reusable_io_slice_cap: NonZeroUsize) -> Self
     where
         R: AsyncRead + Send + 'static,
         W: AsyncWrite + Send + 'static,
     {
-        let shared_data = SharedData::default();
+        Self::builder()
+            .reusable_io_slice_cap(reusable_io_slice_cap)
+            .build(rx, tx)
+    }
 
-        Self {
-            write_task: create_write_task(tx, shared_data.clone(), reusable_io_slice_cap),
-            read_task: create_read_task(rx, shared_data.clone()),
-            shared_data,
-        }
+    pub fn builder() -> ProxyClientBuilder {
+        ProxyClientBuilder::default()
+    }
+
+    /// Open a new session channel, which can be used to
+    /// execute command or launch subsystem on remote.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe.
+    pub async fn open_session(&self) -> Result<Session, Error> {
+        self.open_session_with_config(self.shared_data.get_channel_config())
+            .await
+    }
+
+    /// Same as [`ProxyClient::open_session`], except that the channel
+    /// is configured by `config` instead of the default one set in
+    /// [`ProxyClientBuilder::channel_config`].
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe.
+    pub async fn open_session_with_config(&self, config: &ChannelConfig) -> Result<Session, Error> {
+        Session::open(&self.shared_data, config).await
+    }
+
+    /// Open a `direct-tcpip` channel, which connects to `host:port`
+    /// from the remote.
+    ///
+    /// * `originator` - address of the peer that initiates the connection,
+    ///   it is only passed to sshd for logging purposes.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe.
+    pub async fn open_direct_tcpip(
+        &self,
+        host: Cow<'_, str>,
+        port: u32,
+        originator: SocketAddr,
+    ) -> Result<ChannelStream, Error> {
+        self.open_direct_tcpip_with_config(
+            host,
+            port,
+            originator,
+            self.shared_data.get_channel_config(),
+        )
+        .await
     }
 
+    /// Same as [`ProxyClient::open_direct_tcpip`], except that the channel
+    /// is configured by `config` instead of the default one set in
+    /// [`ProxyClientBuilder::channel_config`].
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe.
+    pub async fn open_direct_tcpip_with_config(
+        &self,
+        host: Cow<'_, str>,
+        port: u32,
+        originator: SocketAddr,
+        config: &ChannelConfig,
+    ) -> Result<ChannelStream, Error> {
+        let originator_ip_address = originator.ip().to_string();
+
+        ChannelStream::open(
+            &self.shared_data,
+            config,
+            |sender_channel, initial_windows_size, max_packet_size| {
+                DirectTcpIp::new(
+                    sender_channel,
+                    initial_windows_size,
+                    max_packet_size,
+                    host,
+                    port,
+                    Cow::Owned(originator_ip_address),
+                    originator.port().into(),
+                )
+            },
+        )
+        .await
+    }
+
+    /// Open a `direct-streamlocal@openssh.com` channel, which connects to
+    /// the unix socket at `socket_path` on remote.
+    ///
+    /// Returns [`Error::ConnectFailed`] if sshd cannot connect to the socket
+    /// and [`Error::AdministrativelyProhibited`] if sshd disables
+    /// streamlocal forwarding.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe.
+    pub async fn open_direct_streamlocal(
+        &self,
+        socket_path: &Path,
+    ) -> Result<ChannelStream, Error> {
+        self.open_direct_streamlocal_with_config(socket_path, self.shared_data.get_channel_config())
+            .await
+    }
+
+    /// Same as [`ProxyClient::open_direct_streamlocal`], except that the
+    /// channel is configured by `config` instead of the default one set in
+    /// [`ProxyClientBuilder::channel_config`].
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe.
+    pub async fn open_direct_streamlocal_with_config(
+        &self,
+        socket_path: &Path,
+        config: &ChannelConfig,
+    ) -> Result<ChannelStream, Error> {
+        ChannelStream::open(
+            &self.shared_data,
+            config,
+            |sender_channel, initial_windows_size, max_packet_size| {
+                DirectStreamLocal::new(
+                    sender_channel,
+                    initial_windows_size,
+                    max_packet_size,
+                    Cow::Borrowed(socket_path.as_os_str().as_bytes()),
+                )
+            },
+        )
+        .await
+    }
+
+    /// Request sshd to listen on `address:port` on remote and forward
+    /// connections accepted back as `forwarded-tcpip` channels.
+    ///
+    /// If `port` is 0, sshd would allocate one, which can be retrieved
+    /// by [`RemoteForwardListener::port`].
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe.
+    pub async fn request_remote_forward(
+        &self,
+        address: Cow<'_, str>,
+        port: u32,
+    ) -> Result<RemoteForwardListener, Error> {
+        RemoteForwardListener::bind(&self.shared_data, address, port).await
+    }
+
+    /// Request sshd to listen on unix socket `socket_path` on remote and
+    /// forward connections accepted back as
+    /// `forwarded-streamlocal@openssh.com` channels.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe.
+    pub async fn request_remote_streamlocal_forward(
+        &self,
+        socket_path: &Path,
+    ) -> Result<StreamLocalForwardListener, Error> {
+        StreamLocalForwardListener::bind(&self.shared_data, socket_path).await
+    }
+
+    /// Set the handler of `auth-agent@openssh.com` channels, which sshd
+    /// opens once agent forwarding is requested by
+    /// [`Session::request_agent_forwarding`] and a remote process connects
+    /// to the forwarded agent.
+    ///
+    /// The handler is called in the read task, so it should not block,
+    /// e.g. spawn a task to connect the stream to the local agent.
+    ///
+    /// Without a handler, these channels are refused.
+    pub fn set_agent_forwarding_handler<F>(&self, handler: F)
+    where
+        F: Fn(ChannelStream) + Send + Sync + 'static,
+    {
+        self.shared_data
+            .get_forwarding_handlers()
+            .set_agent(Arc::new(handler))
+    }
+
+    /// Set the handler of `x11` channels, which sshd opens once X11
+    /// forwarding is requested by [`Session::request_x11_forwarding`]
+    /// and a remote X11 client connects to the forwarded display.
+    ///
+    /// The handler is called in the read task, so it should not block,
+    /// e.g. spawn a task to connect the stream to the local X display.
+    ///
+    /// Without a handler, these channels are refused.
+    pub fn set_x11_forwarding_handler<F>(&self, handler: F)
+    where
+        F: Fn(ForwardedX11) + Send + Sync + 'static,
+    {
+        self.shared_data
+            .get_forwarding_handlers()
+            .set_x11(Arc::new(handler))
+    }
+
+    /// Wait for all channels to be closed and then shutdown.
+    ///
+    /// Use [`ProxyClient::close_with`] if the channels are not
+    /// guaranteed to be closed by their users or the remote.
+    ///
+    /// If the connection is broken, the error that broke it is returned.
+    ///
+    /// Since that error is also returned to channels still open at that
+    /// time as [`Error::ConnectionBroken`], it is returned wrapped in
+    /// [`Error::ConnectionBroken`] as well if any of them still holds it,
+    /// so look inside it for the cause, e.g. [`Error::KeepaliveTimeout`].
     pub async fn close(self) -> Result<(), Error> {
         drop(self.shared_data);
 
-        self.read_task.await??;
-        self.write_task.await??;
+        self.read_task.await?.map_err(unshare_error)?;
+        self.write_task.await?.map_err(unshare_error)?;
 
         Ok(())
     }
+
+    /// Close all channels that are still open, then shutdown once
+    /// all of them are closed by sshd.
+    ///
+    /// Channels opened by sshd afterwards are refused. The close packet
+    /// implies eof, so writing to the closed channels would fail.
+    ///
+    /// If sshd does not close all channels before `deadline`, then the
+    /// remaining channels are aborted and the connection is shutdown
+    /// without flushing the data not yet written.
+    ///
+    /// Errors are returned the same way as [`ProxyClient::close`].
+    ///
+    /// Returns ids of channels aborted, which can be obtained via
+    /// `channel_id` method of [`Session`], [`Child`], [`ChannelStream`],
+    /// [`ChannelInput`] and [`ChannelOutput`].
+    pub async fn close_with(self, deadline: Instant) -> Result<Vec<u32>, Error> {
+        let Self {
+            shared_data,
+            mut read_task,
+            write_task,
+        } = self;
+
+        shared_data.start_closing();
+
+        let cancellation_token = shared_data.get_cancellation_token().clone();
+        drop(shared_data);
+
+        let res = match tokio::time::timeout_at(deadline.into(), &mut read_task).await {
+            Ok(res) => res?,
+            Err(_elapsed) => {
+                cancellation_token.cancel();
+                read_task.await?
+            }
+        };
+        let aborted = res.map_err(unshare_error)?;
+        write_task.await?.map_err(unshare_error)?;
+
+        Ok(aborted)
+    }
+}
+
+/// Take the error of read/write task back, unless it is still
+/// held by channels.
+fn unshare_error(err: Arc<Error>) -> Error {
+    Arc::try_unwrap(err).unwrap_or_else(Error::ConnectionBroken)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::*;
+    use bytes::{BufMut, BytesMut};
+    use fake_sshd::{connect, connect_with_buffer_size, exec};
+
+    use std::{num::NonZeroU32, time::Duration};
+
+    /// Break the connection by keepalive timeout while a process is
+    /// running, then close the client with the error returned by
+    /// `Child::wait` still held if `hold_error`.
+    async fn close_after_keepalive_timeout(hold_error: bool) -> Error {
+        let (client, mut sshd) = connect(
+            ProxyClient::builder().keepalive(Duration::from_secs(1), NonZeroU32::new(1).unwrap()),
+        );
+
+        // sshd never replies to keepalive requests.
+        let (child, _) = exec(&client, &mut sshd, 0, 1024).await;
+
+        let err = child.wait().await.unwrap_err();
+        assert_matches!(&err, Error::ConnectionBroken(cause) if matches!(**cause, Error::KeepaliveTimeout(1)));
+
+        if !hold_error {
+            drop(err);
+            client.close().await.unwrap_err()
+        } else {
+            let close_err = client.close().await.unwrap_err();
+            drop(err);
+            close_err
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn test_close_returns_terminal_error() {
+        assert_matches!(
+            close_after_keepalive_timeout(false).await,
+            Error::KeepaliveTimeout(1)
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn test_close_returns_terminal_error_held_by_channel() {
+        assert_matches!(
+            close_after_keepalive_timeout(true).await,
+            Error::ConnectionBroken(cause) if matches!(*cause, Error::KeepaliveTimeout(1))
+        );
+    }
+
+    /// Call `close_with` while a process is running, and let sshd
+    /// reply to the close packet if `reply_close`.
+    async fn close_with_running_process(reply_close: bool) -> (Vec<u32>, u32) {
+        let (client, mut sshd) = connect(&ProxyClient::builder());
+        let (child, recipient_channel) = exec(&client, &mut sshd, 0, 1024).await;
+
+        let sshd_task = async {
+            let payload = sshd.expect_packet(SSH_MSG_CHANNEL_CLOSE).await;
+            assert_eq!(&payload[..], 0_u32.to_be_bytes());
+
+            if reply_close {
+                sshd.write_channel_packet(SSH_MSG_CHANNEL_CLOSE, recipient_channel, &[])
+                    .await;
+            }
+
+            // Keep the connection open until the client shuts it down.
+            sshd
+        };
+
+        let deadline = Instant::now() + Duration::from_millis(100);
+        let (aborted, _sshd) = tokio::join!(client.close_with(deadline), sshd_task);
+        drop(child);
+
+        (aborted.unwrap(), recipient_channel)
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_close_with_aborts_after_deadline() {
+        let (aborted, channel_id) = close_with_running_process(false).await;
+        assert_eq!(aborted, [channel_id]);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_close_with_drained() {
+        let (aborted, _) = close_with_running_process(true).await;
+        assert_eq!(aborted, []);
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn test_close_with_open_confirmed_after_eof() {
+        // The buffer is too small for the open request and the
+        // confirmation, so the write task keeps running until sshd
+        // reads the request and the confirmation is read in two parts.
+        let (client, mut sshd) = connect_with_buffer_size(&ProxyClient::builder(), 16);
+
+        // The open request is cancelled before it is confirmed.
+        tokio::time::timeout(Duration::from_secs(1), client.open_session())
+            .await
+            .unwrap_err();
+
+        let sshd_task = async {
+            // The first part of the confirmation is read before
+            // `close_with` marks the write channel as eof.
+            let mut payload = BytesMut::new();
+            payload.put_u32(0);
+            payload.put_u32(1);
+            payload.put_u32(1024);
+            payload.put_u32(1024);
+            sshd.write_packet(SSH_MSG_CHANNEL_OPEN_CONFIRMATION, &payload)
+                .await;
+
+            sshd.expect_packet(SSH_MSG_CHANNEL_OPEN).await;
+            sshd.expect_eof().await;
+        };
+
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let (aborted, ()) = tokio::join!(client.close_with(deadline), sshd_task);
+
+        assert_eq!(aborted.unwrap(), []);
+    }
 }
@@ -1,25 +1,34 @@
 use std::{
     convert::TryInto,
-    num::NonZeroUsize,
+    num::{NonZeroU32, NonZeroUsize},
     pin::Pin,
     sync::{atomic::Ordering::Relaxed, Arc},
 };
 
 use bytes::{Bytes, BytesMut};
 use integer_hasher::IntMap;
+use scopeguard::defer;
+use serde::Serialize;
 use ssh_format::from_bytes;
 use tokio::{io::AsyncRead, pin, select, spawn, task::JoinHandle};
 use tokio_io_utility::read_to_bytes_rng;
 
 use crate::{
+    constants::*,
     proxy_client::{
-        channel::{
-            Completion, MpscBytesChannel, OpenChannelRequestedInner, OpenChannelRes, ProcessStatus,
-        },
-        ChannelDataArenaArc, SharedData,
+        channel::{ChannelData, ChannelRef, Completion, MpscBytesChannel, OpenChannelRes},
+        keepalive::{keepalive_tick, Keepalive, KeepaliveConfig},
+        ChannelDataArenaArc, ChannelStream, ForwardedStreamLocal, ForwardedTcpIp, ForwardedX11,
+        SharedData,
+    },
+    request::{
+        ChannelAdjustWindow, ChannelFailure, ChannelOpenConfirmation, ChannelOpenFailure,
+        GlobalRequestFailure, Request,
+    },
+    response::{
+        ChannelOpen, ChannelRequest, ChannelResponse, ExitStatus, ExtendedDataType,
+        ForwardedStreamLocalData, ForwardedTcpIpData, OpenConfirmation, Response, X11Data,
     },
-    request::ChannelAdjustWindow,
-    response::{ChannelRequest, ChannelResponse, ExtendedDataType, OpenConfirmation, Response},
     Error,
 };
 
@@ -34,12 +43,8 @@ struct PendingRequests {
 struct ChannelIngoingData {
     outgoing_data_arena_arc: ChannelDataArenaArc,
 
-    /// Once this get into zero and `outgoing_data.receivers_count != 0`,
-    /// then read task should send `extend_window_size_packet`.
-    receiver_win_size: u32,
-
-    /// Check [`super::channel::ChannelState::extend_window_size`] for doc.
-    extend_window_size: u32,
+    /// Channel id allocated by sshd.
+    recipient_channel: u32,
 
     pending_requests: PendingRequests,
 
@@ -48,6 +53,7 @@ struct ChannelIngoingData {
     stderr: Option<Arc<MpscBytesChannel>>,
 }
 
+/// Indexed by the channel id allocated by us.
 #[derive(Debug, Default)]
 struct ChannelIngoingMap(IntMap<u32, ChannelIngoingData>);
 
@@ -56,7 +62,7 @@ impl ChannelIngoingMap {
     /// If such entry already exists, return an error.
     fn insert_new(&mut self, channel_id: u32, data: ChannelIngoingData) -> Result<(), Error> {
         if self.0.insert(channel_id, data).is_some() {
-            Err(Error::DuplicateSenderChannel(channel_id))
+            Err(Error::InvalidRecipientChannel(channel_id))
         } else {
             Ok(())
         }
@@ -65,82 +71,130 @@ impl ChannelIngoingMap {
     fn get(&mut self, channel_id: u32) -> Result<&mut ChannelIngoingData, Error> {
         self.0
             .get_mut(&channel_id)
-            .ok_or(Error::InvalidSenderChannel(channel_id))
+            .ok_or(Error::InvalidRecipientChannel(channel_id))
     }
 
     fn remove(&mut self, channel_id: u32) -> Result<ChannelIngoingData, Error> {
         self.0
             .remove(&channel_id)
-            .ok_or(Error::InvalidSenderChannel(channel_id))
+            .ok_or(Error::InvalidRecipientChannel(channel_id))
     }
 
     fn is_empty(&self) -> bool {
         self.0.is_empty()
     }
+
+    /// Send close to all channels, called once `ProxyClient::close_with`
+    /// is called.
+    fn send_close_all(&self, shared_data: &SharedData) {
+        for data in self.0.values() {
+            data.outgoing_data_arena_arc
+                .send_close(data.recipient_channel, shared_data);
+        }
+    }
+
+    /// Mark all channels as aborted, called once the read task exits
+    /// so that nobody would wait on them forever.
+    ///
+    /// Returns ids of the channels that are still open.
+    fn abort_all(&mut self) -> Vec<u32> {
+        self.0
+            .drain()
+            .map(|(channel_id, mut data)| {
+                if let Some(rx) = data.rx.take() {
+                    rx.mark_aborted();
+                }
+                if let Some(stderr) = data.stderr.take() {
+                    stderr.mark_aborted();
+                }
+
+                let channel_data = &data.outgoing_data_arena_arc;
+                channel_data.mark_extended_data_eof(true);
+                channel_data.state.set_channel_aborted();
+
+                channel_id
+            })
+            .collect()
+    }
+}
+
+/// Serialize `request` using `buffer` and push it to the write channel.
+///
+/// This function would not modify any existing data in `buffer`.
+fn push_request<T: Serialize>(
+    shared_data: &SharedData,
+    buffer: &mut BytesMut,
+    request: Request<T>,
+) {
+    let start = buffer.len();
+
+    request
+        .serialize_with_header(buffer, 0)
+        .expect("Serialization should not fail here");
+
+    // After this op, buffer contains [0, start) which
+    // contains the same content before serialization
+    // and bytes contains `start..`
+    let bytes = buffer.split_off(start).freeze();
+
+    shared_data.get_write_channel().push_bytes(bytes);
 }
 
-/// If `is_rx` then `bytes` will be pushed to `rx`.
-/// Otherwise it will be pushed to `stderr`.
+/// If `data_type` is `None` then `bytes` will be pushed to `rx`.
+/// Otherwise it will be pushed to `stderr` or the extended data registered.
 fn handle_incoming_data(
     hashmap: &mut ChannelIngoingMap,
     recipient_channel: u32,
     bytes: Bytes,
     buffer: &mut BytesMut,
     shared_data: &SharedData,
-    is_rx: bool,
+    data_type: Option<ExtendedDataType>,
 ) -> Result<(), Error> {
     let data = hashmap.get(recipient_channel)?;
 
-    let cnt: u32 = bytes.len().try_into().unwrap_or(u32::MAX);
+    let cnt = bytes.len();
 
-    let data_receiver_channel = if is_rx {
-        data.rx.as_ref()
-    } else {
-        data.stderr.as_ref()
+    let data_receiver_channel = match data_type {
+        None => data.rx.clone(),
+        Some(ExtendedDataType::Stderr) => data.stderr.clone(),
+        Some(ExtendedDataType::Other(code)) => data.outgoing_data_arena_arc.get_extended_data(code),
     };
 
+    // Data pushed is released once consumed by the receiver.
     if let Some(channel) = data_receiver_channel {
-        channel.push_bytes(bytes);
+        if channel.push_bytes(bytes) {
+            return Ok(());
+        }
     }
 
-    let receiver_win_size = &mut data.receiver_win_size;
-
-    *receiver_win_size = receiver_win_size.saturating_sub(cnt);
-
-    let outgoing_data = &data.outgoing_data_arena_arc;
-
-    // Extend receiver window if it is 0 and there are still
-    // active receivers
-    if *receiver_win_size == 0 && outgoing_data.receivers_count.load(Relaxed) != 0 {
-        let start = buffer.len();
-
-        ChannelAdjustWindow::new(
-            ChannelDataArenaArc::slot(outgoing_data),
-            data.extend_window_size,
-        )
-        .serialize_with_header(buffer, 0)
-        .unwrap();
-
-        // After this op, buffer contains [0, start) which
-        // contains the same content before extend_from_slice
-        // and bytes contains `start..`
-        let bytes = buffer.split_off(start).freeze();
-
-        shared_data.get_write_channel().push_bytes(bytes);
-
-        *receiver_win_size = data.extend_window_size;
+    // Data not registered or whose receiver is dropped is discarded,
+    // but it still consumes the window.
+    if let Some(bytes_to_add) = data.outgoing_data_arena_arc.release_receiver_window(cnt) {
+        push_request(
+            shared_data,
+            buffer,
+            ChannelAdjustWindow::new(data.recipient_channel, bytes_to_add),
+        );
     }
 
     Ok(())
 }
 
 fn mark_eof(data: &mut ChannelIngoingData) {
+    // sshd would not send any data after eof.
+    data.outgoing_data_arena_arc
+        .receiver_window
+        .lock()
+        .unwrap()
+        .close();
+
     if let Some(rx) = data.rx.take() {
         rx.mark_eof();
     }
     if let Some(stderr) = data.stderr.take() {
         stderr.mark_eof();
     }
+    data.outgoing_data_arena_arc.mark_extended_data_eof(false);
 }
 
 fn handle_request_response(
@@ -191,47 +245,270 @@ fn handle_request_response(
     Ok(())
 }
 
-pub(super) fn create_read_task<R>(rx: R, shared_data: SharedData) -> JoinHandle<Result<(), Error>>
+/// Where the channel opened by sshd is sent to once it is accepted.
+type IncomingChannelDst = Box<dyn FnOnce(ChannelStream) + Send>;
+
+/// Accept the channel opened by sshd if there is anyone interested in it,
+/// otherwise refuse it.
+fn handle_open_channel_request(
+    channel_open: ChannelOpen,
+    data: Bytes,
+    shared_data: &SharedData,
+    buffer: &mut BytesMut,
+    ingoing_channel_map: &mut ChannelIngoingMap,
+) -> Result<(), Error> {
+    let ChannelOpen {
+        channel_type,
+        sender_channel,
+        init_win_size,
+        max_packet_size,
+    } = channel_open;
+
+    if shared_data.is_closing() {
+        push_request(
+            shared_data,
+            buffer,
+            ChannelOpenFailure::new(
+                sender_channel,
+                SSH_OPEN_ADMINISTRATIVELY_PROHIBITED,
+                "Client is being closed",
+            ),
+        );
+        return Ok(());
+    }
+
+    let dst: Result<IncomingChannelDst, _> = match channel_type.as_str() {
+        "forwarded-tcpip" => {
+            let data: ForwardedTcpIpData = from_bytes(&data)?.0;
+
+            match shared_data.get_remote_forwards().get_tcpip(&data) {
+                Some(sender) => Ok(Box::new(move |stream| {
+                    // The listener might be dropped, in which case
+                    // the channel is simply closed.
+                    let _ = sender.send(ForwardedTcpIp {
+                        stream,
+                        originator_address: data.originator_address,
+                        originator_port: data.originator_port,
+                    });
+                })),
+                None => Err((
+                    SSH_OPEN_ADMINISTRATIVELY_PROHIBITED,
+                    "No such remote forwarding",
+                )),
+            }
+        }
+        "forwarded-streamlocal@openssh.com" => {
+            let data: ForwardedStreamLocalData = from_bytes(&data)?.0;
+
+            match shared_data.get_remote_forwards().get_streamlocal(&data) {
+                Some((sender, socket_path)) => Ok(Box::new(move |stream| {
+                    // The listener might be dropped, in which case
+                    // the channel is simply closed.
+                    let _ = sender.send(ForwardedStreamLocal {
+                        stream,
+                        socket_path,
+                    });
+                })),
+                None => Err((
+                    SSH_OPEN_ADMINISTRATIVELY_PROHIBITED,
+                    "No such remote forwarding",
+                )),
+            }
+        }
+        "auth-agent@openssh.com" => match shared_data.get_forwarding_handlers().get_agent() {
+            Some(handler) => Ok(Box::new(move |stream| handler(stream))),
+            None => Err((
+                SSH_OPEN_ADMINISTRATIVELY_PROHIBITED,
+                "Agent forwarding is not enabled",
+            )),
+        },
+        "x11" => match shared_data.get_forwarding_handlers().get_x11() {
+            Some(handler) => {
+                let data: X11Data = from_bytes(&data)?.0;
+
+                Ok(Box::new(move |stream| {
+                    handler(ForwardedX11 {
+                        stream,
+                        originator_address: data.originator_address,
+                        originator_port: data.originator_port,
+                    })
+                }))
+            }
+            None => Err((
+                SSH_OPEN_ADMINISTRATIVELY_PROHIBITED,
+                "X11 forwarding is not enabled",
+            )),
+        },
+        _ => Err((SSH_OPEN_UNKNOWN_CHANNEL_TYPE, "Unknown channel type")),
+    };
+
+    let dst = match dst {
+        Ok(dst) => dst,
+        Err((reason_code, description)) => {
+            push_request(
+                shared_data,
+                buffer,
+                ChannelOpenFailure::new(sender_channel, reason_code, description),
+            );
+            return Ok(());
+        }
+    };
+
+    let max_packet_size_non_zero =
+        NonZeroU32::new(max_packet_size).ok_or(Error::InvalidResponse(&"max_packet_size is 0"))?;
+
+    let config = shared_data.get_channel_config();
+    let channel_data = match shared_data.try_insert_channel_data(ChannelData::new(false, config)) {
+        Some(channel_data) => channel_data,
+        None => {
+            push_request(
+                shared_data,
+                buffer,
+                ChannelOpenFailure::new(
+                    sender_channel,
+                    SSH_OPEN_RESOURCE_SHORTAGE,
+                    "Too many channels",
+                ),
+            );
+            return Ok(());
+        }
+    };
+    let channel_id = ChannelDataArenaArc::slot(&channel_data);
+
+    channel_data.sender_window_size.add(init_win_size.into());
+
+    channel_data
+        .state
+        .set_channel_open_res(OpenChannelRes::Confirmed {
+            recipient_channel: sender_channel,
+            max_packet_size,
+        })?;
+
+    ingoing_channel_map.insert_new(
+        channel_id,
+        ChannelIngoingData {
+            rx: channel_data.rx.clone(),
+            stderr: channel_data.stderr.clone(),
+
+            outgoing_data_arena_arc: channel_data.clone(),
+            recipient_channel: sender_channel,
+
+            pending_requests: Default::default(),
+        },
+    )?;
+
+    // Confirmation must be sent before any data is written
+    // to the channel.
+    push_request(
+        shared_data,
+        buffer,
+        ChannelOpenConfirmation::new(
+            sender_channel,
+            channel_id,
+            config.get_window_size(),
+            config.get_max_packet_size(),
+        ),
+    );
+
+    let channel_ref = ChannelRef::new(shared_data.clone(), channel_data, sender_channel);
+    dst(ChannelStream::new(channel_ref, max_packet_size_non_zero));
+
+    Ok(())
+}
+
+pub(super) fn create_read_task<R>(
+    rx: R,
+    shared_data: SharedData,
+    keepalive: Option<KeepaliveConfig>,
+) -> JoinHandle<Result<Vec<u32>, Arc<Error>>>
 where
     R: AsyncRead + Send + 'static,
 {
     spawn(async move {
         pin!(rx);
 
-        create_read_task_inner(rx, shared_data).await
+        let mut ingoing_channel_map = ChannelIngoingMap::default();
+
+        // Declared before the cancellation guard so that it runs after
+        // the cancellation token is cancelled on panic or error, then no
+        // global request could be sent after the callbacks are dropped.
+        defer! {
+            // Wake up everyone waiting for global requests or
+            // remote forwarding.
+            shared_data.get_global_requests().clear();
+            shared_data.get_remote_forwards().clear();
+        }
+
+        // Cancel on panic or error, after the error is recorded.
+        let cancellation_guard = shared_data.get_cancellation_token().clone().drop_guard();
+
+        let res = create_read_task_inner(rx, &shared_data, &mut ingoing_channel_map, keepalive)
+            .await
+            .map_err(|err| shared_data.set_terminal_error(err));
+
+        if res.is_ok() {
+            cancellation_guard.disarm();
+        } else {
+            drop(cancellation_guard);
+        }
+
+        // Channels are still open only if the read task failed
+        // or is cancelled.
+        let aborted = ingoing_channel_map.abort_all();
+
+        res.map(|_| aborted)
     })
 }
 
 async fn create_read_task_inner(
     mut rx: Pin<&mut (dyn AsyncRead + Send)>,
-    shared_data: SharedData,
+    shared_data: &SharedData,
+    ingoing_channel_map: &mut ChannelIngoingMap,
+    keepalive: Option<KeepaliveConfig>,
 ) -> Result<(), Error> {
     let mut buffer = BytesMut::with_capacity(1024);
-    let mut ingoing_channel_map = ChannelIngoingMap::default();
+    let mut keepalive = keepalive.map(Keepalive::new);
 
     let notified = shared_data.get_read_task_shutdown_notifier().notified();
     pin!(notified);
 
     notified.as_mut().enable();
 
-    let cancellation_guard = shared_data.get_cancellation_token().clone().drop_guard();
+    let cancellation_token = shared_data.get_cancellation_token();
+
+    let mut is_closing = false;
 
     loop {
         select! {
             biased;
 
+            // Channels still open would be aborted.
+            _ = cancellation_token.cancelled() => break,
+
             res = read_and_handle_one_packet(
                 rx.as_mut(),
-                &shared_data,
+                shared_data,
                 &mut buffer,
-                &mut ingoing_channel_map,
+                ingoing_channel_map,
             ) => res?,
 
+            res = keepalive_tick(keepalive.as_mut(), shared_data) => res?,
+
+            _ = shared_data.get_close_requested_notifier().notified(), if !is_closing => {
+                is_closing = true;
+                ingoing_channel_map.send_close_all(shared_data);
+            }
+
             _ = &mut notified, if ingoing_channel_map.is_empty() => break ,
         }
-    }
 
-    cancellation_guard.disarm();
+        if is_closing && ingoing_channel_map.is_empty() {
+            // All channels are drained, so shutdown the write task,
+            // which would notify the read task once everything is
+            // flushed.
+            shared_data.get_write_channel().mark_eof();
+        }
+    }
 
     Ok(())
 }
@@ -242,7 +519,10 @@ async fn read_and_handle_one_packet(
     buffer: &mut BytesMut,
     ingoing_channel_map: &mut ChannelIngoingMap,
 ) -> Result<(), Error> {
-    read_to_bytes_rng(&mut rx, buffer, 4..).await?;
+    if buffer.len() < 4 {
+        let n = 4 - buffer.len();
+        read_to_bytes_rng(&mut rx, buffer, n..).await?;
+    }
 
     let packet_len: u32 = from_bytes(&buffer[..4])?.0;
     let packet_len: usize = packet_len.try_into().unwrap();
@@ -259,125 +539,355 @@ async fn read_and_handle_one_packet(
     // and the returned bytes contains``..(packet_len + 4)`.
     let response = Response::from_bytes(buffer.split_to(packet_len + 4).freeze().slice(4..))?;
 
-    if let Response::ChannelResponse {
-        channel_response,
-        recipient_channel,
-    } = response
-    {
-        match channel_response {
-            // Handle response to open channel request
-            ChannelResponse::OpenConfirmation(OpenConfirmation {
-                sender_channel,
-                init_win_size,
-                max_packet_size,
-            }) => {
-                let outgoing_data_arena_arc = shared_data.get_channel_data(recipient_channel)?;
+    match response {
+        Response::ChannelResponse {
+            channel_response,
+            recipient_channel,
+        } => {
+            match channel_response {
+                // Handle response to open channel request
+                ChannelResponse::OpenConfirmation(OpenConfirmation {
+                    sender_channel,
+                    init_win_size,
+                    max_packet_size,
+                }) => {
+                    let outgoing_data_arena_arc =
+                        shared_data.get_channel_data(recipient_channel)?;
+
+                    outgoing_data_arena_arc
+                        .sender_window_size
+                        .add(init_win_size.into());
+
+                    outgoing_data_arena_arc.state.set_channel_open_res(
+                        OpenChannelRes::Confirmed {
+                            recipient_channel: sender_channel,
+                            max_packet_size,
+                        },
+                    )?;
+
+                    if shared_data.is_closing() {
+                        if shared_data.get_write_channel().is_eof() {
+                            // Every other channel is drained and the write
+                            // task is being shutdown, so nothing could be
+                            // sent anymore and the channel would be closed
+                            // along with the connection.
+                            shared_data.remove_channel_data(recipient_channel)?;
+                            return Ok(());
+                        }
+
+                        // The open request is cancelled by its initiator
+                        // and the channel is confirmed after
+                        // `ProxyClient::close_with` is called, so nobody
+                        // would close it.
+                        outgoing_data_arena_arc.send_close(sender_channel, shared_data);
+                    }
 
-                outgoing_data_arena_arc
-                    .sender_window_size
-                    .add(init_win_size.try_into().unwrap());
+                    let ingoing_data = ChannelIngoingData {
+                        rx: outgoing_data_arena_arc.rx.clone(),
+                        stderr: outgoing_data_arena_arc.stderr.clone(),
 
-                let OpenChannelRequestedInner {
-                    init_receiver_win_size,
-                    extend_window_size,
-                } = outgoing_data_arena_arc
-                    .state
-                    .set_channel_open_res(OpenChannelRes::Confirmed { max_packet_size })?;
+                        outgoing_data_arena_arc,
+                        recipient_channel: sender_channel,
 
-                let ingoing_data = ChannelIngoingData {
-                    rx: outgoing_data_arena_arc.rx.clone(),
-                    stderr: outgoing_data_arena_arc.stderr.clone(),
+                        pending_requests: Default::default(),
+                    };
 
-                    outgoing_data_arena_arc,
-                    receiver_win_size: init_receiver_win_size,
-                    extend_window_size,
+                    ingoing_channel_map.insert_new(recipient_channel, ingoing_data)?;
+                }
+                ChannelResponse::OpenFailure(failure) => {
+                    shared_data
+                        .get_channel_data(recipient_channel)?
+                        .state
+                        .set_channel_open_res(OpenChannelRes::Failed(failure))?;
+                }
 
-                    pending_requests: Default::default(),
-                };
+                // Handle close of the channel
+                ChannelResponse::Close => {
+                    let mut data = ingoing_channel_map.remove(recipient_channel)?;
 
-                ingoing_channel_map.insert_new(sender_channel, ingoing_data)?;
-            }
-            ChannelResponse::OpenFailure(failure) => {
-                shared_data
-                    .get_channel_data(recipient_channel)?
-                    .state
-                    .set_channel_open_res(OpenChannelRes::Failed(failure))?;
-            }
+                    mark_eof(&mut data);
 
-            // Handle close of the channel
-            ChannelResponse::Close => {
-                let mut data = ingoing_channel_map.remove(recipient_channel)?;
+                    // Wake up `Child::wait` if sshd does not send the exit status.
+                    data.outgoing_data_arena_arc.state.set_channel_closed();
 
-                mark_eof(&mut data);
-            }
+                    // Reply right away instead of waiting for every
+                    // `ChannelRef` to be dropped, otherwise the channel
+                    // would be half-closed as long as the user holds it.
+                    data.outgoing_data_arena_arc
+                        .send_close(data.recipient_channel, shared_data);
 
-            // Handle data related responses
-            ChannelResponse::BytesAdjust { bytes_to_add } => ingoing_channel_map
-                .get(recipient_channel)?
-                .outgoing_data_arena_arc
-                .sender_window_size
-                .add(bytes_to_add.try_into().unwrap()),
-            ChannelResponse::Data(bytes) => handle_incoming_data(
-                ingoing_channel_map,
-                recipient_channel,
-                bytes,
-                buffer,
-                shared_data,
-                true,
-            )?,
-            ChannelResponse::ExtendedData { data_type, data } => {
-                if let ExtendedDataType::Stderr = data_type {
-                    handle_incoming_data(
-                        ingoing_channel_map,
-                        recipient_channel,
-                        data,
-                        buffer,
-                        shared_data,
-                        false,
-                    )?
+                    // The channel is now closed, so the slot can be reused
+                    // once every `ChannelRef` to it is dropped.
+                    shared_data.remove_channel_data(recipient_channel)?;
+                }
+
+                // Handle data related responses
+                ChannelResponse::BytesAdjust { bytes_to_add } => ingoing_channel_map
+                    .get(recipient_channel)?
+                    .outgoing_data_arena_arc
+                    .sender_window_size
+                    .add(bytes_to_add.into()),
+                ChannelResponse::Data(bytes) => handle_incoming_data(
+                    ingoing_channel_map,
+                    recipient_channel,
+                    bytes,
+                    buffer,
+                    shared_data,
+                    None,
+                )?,
+                ChannelResponse::ExtendedData { data_type, data } => handle_incoming_data(
+                    ingoing_channel_map,
+                    recipient_channel,
+                    data,
+                    buffer,
+                    shared_data,
+                    Some(data_type),
+                )?,
+                ChannelResponse::Eof => mark_eof(ingoing_channel_map.get(recipient_channel)?),
+
+                // Handle responses to requests
+                ChannelResponse::RequestSuccess => {
+                    handle_request_response(ingoing_channel_map, recipient_channel, true)?
+                }
+                ChannelResponse::RequestFailure => {
+                    handle_request_response(ingoing_channel_map, recipient_channel, false)?
                 }
-            }
-            ChannelResponse::Eof => mark_eof(ingoing_channel_map.get(recipient_channel)?),
 
-            // Handle responses to requests
-            ChannelResponse::RequestSuccess => {
-                handle_request_response(ingoing_channel_map, recipient_channel, true)?
+                // Handle incoming requests from sshd
+                ChannelResponse::Request(request) => {
+                    let ingoing_data = ingoing_channel_map.get(recipient_channel)?;
+
+                    let process_status = match request {
+                        ChannelRequest::StatusCode(code) => ExitStatus::Exited(code),
+                        ChannelRequest::KilledBySignal(exit_signal) => {
+                            ExitStatus::Killed(exit_signal)
+                        }
+                        ChannelRequest::EndOfWrite => {
+                            let channel_data = &ingoing_data.outgoing_data_arena_arc;
+
+                            channel_data.eow_received.store(true, Relaxed);
+
+                            // Wake up `ChannelInput` waiting for window size
+                            // so that it would return error.
+                            channel_data.sender_window_size.add(0);
+
+                            return Ok(());
+                        }
+                        ChannelRequest::Unknown { want_reply } => {
+                            if want_reply {
+                                push_request(
+                                    shared_data,
+                                    buffer,
+                                    ChannelFailure::new(ingoing_data.recipient_channel),
+                                );
+                            }
+
+                            return Ok(());
+                        }
+                    };
+
+                    ingoing_data
+                        .outgoing_data_arena_arc
+                        .state
+                        .set_channel_process_status(process_status);
+                }
             }
-            ChannelResponse::RequestFailure => {
-                handle_request_response(ingoing_channel_map, recipient_channel, false)?
+        }
+
+        // Handle global requests from sshd, e.g. `keepalive@openssh.com`
+        Response::GlobalRequest { want_reply } => {
+            if want_reply {
+                push_request(shared_data, buffer, GlobalRequestFailure::new());
             }
+        }
 
-            // Handle incoming requests from sshd (exit status)
-            ChannelResponse::Request(request) => {
-                let process_status = match request {
-                    ChannelRequest::StatusCode(exit_status) => {
-                        ProcessStatus::ProcessExited(exit_status)
-                    }
-                    ChannelRequest::KilledBySignal(exit_signal) => {
-                        ProcessStatus::ProcessKilled(exit_signal)
-                    }
-                    _ => {
-                        return Err(Error::UnexpectedChannelState {
-                            expected_state:
-                                &"ChannelResponse::Request(StatusCode | KilledBySignal)",
-                            actual_state: "ChannelResponse::Request(Unknown)",
-                        })
-                    }
-                };
+        // Handle replies to global requests
+        Response::GlobalRequestSuccess(data) => {
+            shared_data.get_global_requests().report_reply(Some(data))?
+        }
+        Response::GlobalRequestFailure => shared_data.get_global_requests().report_reply(None)?,
+
+        // Handle channels opened by sshd
+        Response::OpenChannelRequest { channel_open, data } => handle_open_channel_request(
+            channel_open,
+            data,
+            shared_data,
+            buffer,
+            ingoing_channel_map,
+        )?,
+    }
 
-                ingoing_channel_map
-                    .get(recipient_channel)?
-                    .outgoing_data_arena_arc
-                    .state
-                    .set_channel_process_status(process_status)?;
-            }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        fake_sshd::{connect, exec, exec_session, open_session, put_string, FakeSshd},
+        ChannelConfig, ProxyClient,
+    };
+    use super::*;
+
+    use bytes::BufMut;
+    use tokio::io::AsyncReadExt;
+
+    /// Build the payload of a channel request sent by sshd.
+    fn channel_request(request_type: &str, want_reply: bool, data: &[u8]) -> BytesMut {
+        let mut buffer = BytesMut::new();
+        put_string(&mut buffer, request_type.as_bytes());
+        buffer.put_u8(want_reply.into());
+        buffer.put_slice(data);
+        buffer
+    }
+
+    async fn send_global_request(sshd: &mut FakeSshd, want_reply: bool) {
+        let mut buffer = BytesMut::new();
+        put_string(&mut buffer, b"unknown@example.com");
+        buffer.put_u8(want_reply.into());
+
+        sshd.write_packet(SSH_MSG_GLOBAL_REQUEST, &buffer).await;
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_reply_close() {
+        let (client, mut sshd) = connect(&ProxyClient::builder());
+        let (child, recipient_channel) = exec(&client, &mut sshd, 7, 1024).await;
+
+        sshd.write_channel_packet(SSH_MSG_CHANNEL_CLOSE, recipient_channel, &[])
+            .await;
+
+        // The close is replied while the child is still held.
+        let payload = sshd.expect_packet(SSH_MSG_CHANNEL_CLOSE).await;
+        assert_eq!(&payload[..], 7_u32.to_be_bytes());
+
+        drop(child);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_reply_failure_to_unknown_global_request() {
+        let (_client, mut sshd) = connect(&ProxyClient::builder());
+
+        // No reply is sent unless it is wanted, so the failure
+        // received is the reply to the second request.
+        send_global_request(&mut sshd, false).await;
+        send_global_request(&mut sshd, true).await;
+
+        let (packet_type, payload) = sshd.read_packet().await;
+        assert_eq!(packet_type, SSH_MSG_REQUEST_FAILURE);
+        assert!(payload.is_empty());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_reply_failure_to_unknown_channel_request() {
+        let (client, mut sshd) = connect(&ProxyClient::builder());
+        let (_child, recipient_channel) = exec(&client, &mut sshd, 7, 1024).await;
+
+        for want_reply in [false, true] {
+            sshd.write_channel_packet(
+                SSH_MSG_CHANNEL_REQUEST,
+                recipient_channel,
+                &channel_request("unknown@example.com", want_reply, &[]),
+            )
+            .await;
+        }
+
+        let (packet_type, payload) = sshd.read_packet().await;
+        assert_eq!(packet_type, SSH_MSG_CHANNEL_FAILURE);
+        assert_eq!(&payload[..], 7_u32.to_be_bytes());
+    }
+
+    async fn send_extended_data(
+        sshd: &mut FakeSshd,
+        recipient_channel: u32,
+        data_type: u32,
+        data: &[u8],
+    ) {
+        let mut buffer = BytesMut::new();
+        buffer.put_u32(data_type);
+        put_string(&mut buffer, data);
+
+        sshd.write_channel_packet(SSH_MSG_CHANNEL_EXTENDED_DATA, recipient_channel, &buffer)
+            .await;
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_extended_data() {
+        let (client, mut sshd) = connect(&ProxyClient::builder());
+
+        let mut config = ChannelConfig::new();
+        config.window_size(NonZeroU32::new(1024).unwrap());
+
+        let (mut session, recipient_channel) =
+            open_session(&client, &mut sshd, 0, 1024, &config).await;
+        let mut registered = session.register_extended_data(2).unwrap();
+        let mut child = exec_session(session, &mut sshd, 0, recipient_channel).await;
+        let mut stderr = child.stderr.take().unwrap();
+
+        send_extended_data(
+            &mut sshd,
+            recipient_channel,
+            SSH_EXTENDED_DATA_STDERR,
+            b"err",
+        )
+        .await;
+        send_extended_data(&mut sshd, recipient_channel, 2, b"two").await;
+
+        // Data of type not registered is discarded, releasing the
+        // window it takes right away.
+        send_extended_data(&mut sshd, recipient_channel, 3, &[0; 600]).await;
+
+        let payload = sshd.expect_packet(SSH_MSG_CHANNEL_WINDOW_ADJUST).await;
+        let (channel, bytes_to_add): (u32, u32) = from_bytes(&payload).unwrap().0;
+        assert_eq!(channel, 0);
+        assert_eq!(bytes_to_add, 600);
+
+        let mut buffer = [0_u8; 3];
+        stderr.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"err");
+        registered.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"two");
+
+        // Eof is delivered to every output, including the registered one.
+        sshd.write_channel_packet(SSH_MSG_CHANNEL_EOF, recipient_channel, &[])
+            .await;
+
+        let mut buffer = Vec::new();
+        stderr.read_to_end(&mut buffer).await.unwrap();
+        registered.read_to_end(&mut buffer).await.unwrap();
+        assert!(buffer.is_empty());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_duplicate_exit_status() {
+        let (client, mut sshd) = connect(&ProxyClient::builder());
+        let (child, recipient_channel) = exec(&client, &mut sshd, 0, 1024).await;
+
+        for exit_status in [1_u32, 2] {
+            sshd.write_channel_packet(
+                SSH_MSG_CHANNEL_REQUEST,
+                recipient_channel,
+                &channel_request("exit-status", false, &exit_status.to_be_bytes()),
+            )
+            .await;
         }
 
-        Ok(())
-    } else {
-        Err(Error::UnexpectedChannelState {
-            expected_state: &"ChannelResponse",
-            actual_state: response.into(),
-        })
+        let mut buffer = BytesMut::new();
+        put_string(&mut buffer, b"SEGV");
+        buffer.put_u8(1);
+        put_string(&mut buffer, b"");
+        put_string(&mut buffer, b"");
+        sshd.write_channel_packet(
+            SSH_MSG_CHANNEL_REQUEST,
+            recipient_channel,
+            &channel_request("exit-signal", false, &buffer),
+        )
+        .await;
+
+        assert_matches!(child.wait().await, Ok(Some(ExitStatus::Exited(1))));
+
+        // The connection is still alive.
+        let (child, _) = exec(&client, &mut sshd, 1, 1024).await;
+        drop(child);
+        sshd.expect_packet(SSH_MSG_CHANNEL_CLOSE).await;
     }
 }
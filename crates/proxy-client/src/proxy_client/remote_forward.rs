@@ -0,0 +1,364 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    ffi::OsStr,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+};
+
+use compact_str::CompactString;
+use futures_util::stream::Stream;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use super::{ChannelStream, SharedData};
+use crate::{
+    request::{StreamLocalForward, TcpipForward},
+    response::{ForwardedStreamLocalData, ForwardedTcpIpData},
+    Error,
+};
+
+/// Connection accepted by [`RemoteForwardListener`].
+#[derive(Debug)]
+pub struct ForwardedTcpIp {
+    pub stream: ChannelStream,
+
+    /// Address of the peer connected to the forwarded port.
+    pub originator_address: CompactString,
+    pub originator_port: u32,
+}
+
+/// Connection accepted by [`StreamLocalForwardListener`].
+#[derive(Debug)]
+pub struct ForwardedStreamLocal {
+    pub stream: ChannelStream,
+
+    /// Path of the remote unix socket the connection is accepted on.
+    pub socket_path: PathBuf,
+}
+
+/// `(address_to_bind, port)` of the remote forwarding.
+type TcpipForwardKey = (CompactString, u32);
+
+/// Remote forwardings established, used by the read task to dispatch
+/// channels opened by sshd.
+#[derive(Debug, Default)]
+pub(super) struct RemoteForwards {
+    tcpip: Mutex<HashMap<TcpipForwardKey, UnboundedSender<ForwardedTcpIp>>>,
+    streamlocal: Mutex<HashMap<PathBuf, UnboundedSender<ForwardedStreamLocal>>>,
+}
+
+impl RemoteForwards {
+    /// Return the sender of the listener `data` is forwarded to.
+    pub(super) fn get_tcpip(
+        &self,
+        data: &ForwardedTcpIpData,
+    ) -> Option<UnboundedSender<ForwardedTcpIp>> {
+        self.tcpip
+            .lock()
+            .unwrap()
+            .get(&(data.connected_address.clone(), data.connected_port))
+            .cloned()
+    }
+
+    /// Return the sender of the listener `data` is forwarded to, together
+    /// with the path of the socket.
+    pub(super) fn get_streamlocal(
+        &self,
+        data: &ForwardedStreamLocalData,
+    ) -> Option<(UnboundedSender<ForwardedStreamLocal>, PathBuf)> {
+        let socket_path = Path::new(OsStr::from_bytes(&data.socket_path));
+
+        self.streamlocal
+            .lock()
+            .unwrap()
+            .get(socket_path)
+            .map(|sender| (sender.clone(), socket_path.to_path_buf()))
+    }
+
+    /// Remove all listeners, called by the read task on exit so that
+    /// they would return `None`.
+    pub(super) fn clear(&self) {
+        let tcpip = std::mem::take(&mut *self.tcpip.lock().unwrap());
+        let streamlocal = std::mem::take(&mut *self.streamlocal.lock().unwrap());
+
+        // Drop the senders outside of the critical section.
+        drop(tcpip);
+        drop(streamlocal);
+    }
+}
+
+/// Listener of remote forwarding, created by
+/// [`ProxyClient::request_remote_forward`](super::ProxyClient::request_remote_forward).
+///
+/// Dropping it cancels the forwarding.
+#[derive(Debug)]
+pub struct RemoteForwardListener {
+    shared_data: SharedData,
+    key: TcpipForwardKey,
+    receiver: UnboundedReceiver<ForwardedTcpIp>,
+}
+
+impl RemoteForwardListener {
+    /// Request sshd to listen on `address:port` and wait for its reply.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe.
+    pub(super) async fn bind(
+        shared_data: &SharedData,
+        address: Cow<'_, str>,
+        port: u32,
+    ) -> Result<Self, Error> {
+        let (sender, receiver) = unbounded_channel();
+
+        let address = CompactString::from(address);
+        let key_address = address.clone();
+        let shared_data_cloned = shared_data.clone();
+
+        // Register the listener in the read task right after receiving
+        // the reply, so that no `forwarded-tcpip` channel would be missed.
+        //
+        // If the request is cancelled, the listener is dropped in the read
+        // task, which cancels the forwarding.
+        shared_data
+            .send_global_request_and_wait(
+                TcpipForward::new(Cow::Borrowed(&address), port),
+                move |reply| {
+                    let data = reply.ok_or(Error::GlobalRequestFailure(&"tcpip-forward"))?;
+
+                    let port = if port == 0 {
+                        // sshd replies with the port allocated.
+                        ssh_format::from_bytes(&data)?.0
+                    } else {
+                        port
+                    };
+                    let key = (key_address, port);
+
+                    shared_data_cloned
+                        .get_remote_forwards()
+                        .tcpip
+                        .lock()
+                        .unwrap()
+                        .insert(key.clone(), sender);
+
+                    Ok(Self {
+                        shared_data: shared_data_cloned,
+                        key,
+                        receiver,
+                    })
+                },
+            )
+            .await
+    }
+
+    /// Port sshd listens on, which is allocated by sshd if `0` is passed
+    /// to [`ProxyClient::request_remote_forward`](super::ProxyClient::request_remote_forward).
+    pub fn port(&self) -> u32 {
+        self.key.1
+    }
+
+    /// Wait for a new connection.
+    ///
+    /// Returns `None` if the connection to sshd is closed.
+    pub async fn accept(&mut self) -> Option<ForwardedTcpIp> {
+        self.receiver.recv().await
+    }
+}
+
+impl Stream for RemoteForwardListener {
+    type Item = ForwardedTcpIp;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for RemoteForwardListener {
+    fn drop(&mut self) {
+        let sender = self
+            .shared_data
+            .get_remote_forwards()
+            .tcpip
+            .lock()
+            .unwrap()
+            .remove(&self.key);
+
+        if sender.is_some() {
+            let (address, port) = &self.key;
+
+            // Serialization should not fail here, and there is nothing we
+            // can do inside drop anyway.
+            let _ = self
+                .shared_data
+                .send_global_request_no_reply(TcpipForward::cancel(Cow::Borrowed(address), *port));
+        }
+    }
+}
+
+/// Listener of remote unix socket forwarding, created by
+/// [`ProxyClient::request_remote_streamlocal_forward`](super::ProxyClient::request_remote_streamlocal_forward).
+///
+/// Dropping it cancels the forwarding.
+#[derive(Debug)]
+pub struct StreamLocalForwardListener {
+    shared_data: SharedData,
+    socket_path: PathBuf,
+    receiver: UnboundedReceiver<ForwardedStreamLocal>,
+}
+
+impl StreamLocalForwardListener {
+    /// Request sshd to listen on `socket_path` and wait for its reply.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe.
+    pub(super) async fn bind(shared_data: &SharedData, socket_path: &Path) -> Result<Self, Error> {
+        let (sender, receiver) = unbounded_channel();
+
+        let key = socket_path.to_path_buf();
+        let shared_data_cloned = shared_data.clone();
+
+        // Register the listener in the read task right after receiving
+        // the reply, so that no `forwarded-streamlocal@openssh.com`
+        // channel would be missed.
+        //
+        // If the request is cancelled, the listener is dropped in the read
+        // task, which cancels the forwarding.
+        shared_data
+            .send_global_request_and_wait(
+                StreamLocalForward::new(Cow::Borrowed(socket_path.as_os_str().as_bytes())),
+                move |reply| {
+                    reply.ok_or(Error::GlobalRequestFailure(
+                        &"streamlocal-forward@openssh.com",
+                    ))?;
+
+                    shared_data_cloned
+                        .get_remote_forwards()
+                        .streamlocal
+                        .lock()
+                        .unwrap()
+                        .insert(key.clone(), sender);
+
+                    Ok(Self {
+                        shared_data: shared_data_cloned,
+                        socket_path: key,
+                        receiver,
+                    })
+                },
+            )
+            .await
+    }
+
+    /// Path of the remote unix socket sshd listens on.
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    /// Wait for a new connection.
+    ///
+    /// Returns `None` if the connection to sshd is closed.
+    pub async fn accept(&mut self) -> Option<ForwardedStreamLocal> {
+        self.receiver.recv().await
+    }
+}
+
+impl Stream for StreamLocalForwardListener {
+    type Item = ForwardedStreamLocal;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for StreamLocalForwardListener {
+    fn drop(&mut self) {
+        let sender = self
+            .shared_data
+            .get_remote_forwards()
+            .streamlocal
+            .lock()
+            .unwrap()
+            .remove(&self.socket_path);
+
+        if sender.is_some() {
+            // Serialization should not fail here, and there is nothing we
+            // can do inside drop anyway.
+            let _ = self
+                .shared_data
+                .send_global_request_no_reply(StreamLocalForward::cancel(Cow::Borrowed(
+                    self.socket_path.as_os_str().as_bytes(),
+                )));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{fake_sshd::connect, ProxyClient};
+    use super::*;
+    use crate::constants::*;
+
+    use std::io;
+
+    fn is_unexpected_eof(err: &Error) -> bool {
+        matches!(err, Error::ConnectionBroken(cause) if matches!(&**cause, Error::IOError(err) if err.kind() == io::ErrorKind::UnexpectedEof))
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_bind_after_connection_broken() {
+        let (client, sshd) = connect(&ProxyClient::builder());
+        drop(sshd);
+
+        // Wait for the read task to exit.
+        client
+            .shared_data
+            .get_cancellation_token()
+            .cancelled()
+            .await;
+
+        let err = client
+            .request_remote_forward(Cow::Borrowed("127.0.0.1"), 0)
+            .await
+            .unwrap_err();
+        assert!(is_unexpected_eof(&err), "{:?}", err);
+
+        let err = client
+            .request_remote_streamlocal_forward(Path::new("/tmp/socket"))
+            .await
+            .unwrap_err();
+        assert!(is_unexpected_eof(&err), "{:?}", err);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_bind_connection_broken_before_reply() {
+        let (client, mut sshd) = connect(&ProxyClient::builder());
+
+        let sshd_task = async move {
+            sshd.expect_packet(SSH_MSG_GLOBAL_REQUEST).await;
+        };
+
+        let (res, ()) = tokio::join!(
+            client.request_remote_forward(Cow::Borrowed("127.0.0.1"), 0),
+            sshd_task
+        );
+        assert!(is_unexpected_eof(&res.unwrap_err()));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_bind_streamlocal_connection_broken_before_reply() {
+        let (client, mut sshd) = connect(&ProxyClient::builder());
+
+        let sshd_task = async move {
+            sshd.expect_packet(SSH_MSG_GLOBAL_REQUEST).await;
+        };
+
+        let (res, ()) = tokio::join!(
+            client.request_remote_streamlocal_forward(Path::new("/tmp/socket")),
+            sshd_task
+        );
+        assert!(is_unexpected_eof(&res.unwrap_err()));
+    }
+}
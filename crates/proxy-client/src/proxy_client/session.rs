@@ -0,0 +1,321 @@
+use std::{
+    borrow::Cow,
+    num::{NonZeroU32, NonZeroUsize},
+};
+
+use bytes::BytesMut;
+use serde::Serialize;
+
+use super::{
+    channel::{ChannelAborted, ChannelConfig, ChannelInput, ChannelOutput, ChannelRef, Completion},
+    SharedData,
+};
+use crate::{
+    constants::SSH_EXTENDED_DATA_STDERR,
+    request::{
+        self, ChannelRequest, ExecCmd, PassEnv, Request, RequestAgentForwarding, RequestPty,
+        RequestShell, RequestSubsystem, RequestX11Forwarding, SendSignal, WindowChange,
+    },
+    Error, ExitStatus, NonZeroByteSlice, SignalName, TerminalModes, WindowSize,
+};
+
+/// A session channel that has not launched any process yet.
+///
+/// Dropping it would close the channel.
+///
+/// # Cancel safety
+///
+/// All methods of this struct is not cancellation safe.
+#[derive(Debug)]
+pub struct Session {
+    channel_ref: ChannelRef,
+    max_packet_size: NonZeroU32,
+}
+
+impl Session {
+    pub(super) async fn open(
+        shared_data: &SharedData,
+        config: &ChannelConfig,
+    ) -> Result<Self, Error> {
+        let (channel_ref, max_packet_size) =
+            ChannelRef::open(shared_data, true, config, request::Session::new).await?;
+
+        Ok(Self {
+            channel_ref,
+            max_packet_size,
+        })
+    }
+
+    /// Id of the session channel.
+    pub fn channel_id(&self) -> u32 {
+        self.channel_ref.channel_id()
+    }
+
+    /// Send the request and wait for sshd to reply.
+    async fn send_request<T: Serialize>(
+        &mut self,
+        request: Request<ChannelRequest<T>>,
+    ) -> Result<(), Error> {
+        let mut buffer = BytesMut::new();
+        request.serialize_with_header(&mut buffer, 0)?;
+
+        let pending_requests = &self.channel_ref.channel_data.pending_requests;
+
+        pending_requests
+            .start_new_requests(NonZeroUsize::new(1).unwrap())
+            .await;
+
+        self.channel_ref
+            .shared_data
+            .get_write_channel()
+            .push_bytes(buffer.freeze());
+
+        let shared_data = &self.channel_ref.shared_data;
+
+        tokio::select! {
+            biased;
+
+            completion = pending_requests.wait_for_completion() => match completion {
+                Completion::Success => Ok(()),
+                Completion::Failed => Err(Error::ChannelRequestFailure(request.request_type())),
+            },
+            _ = shared_data.get_cancellation_token().cancelled() => {
+                Err(shared_data.get_terminal_error())
+            }
+        }
+    }
+
+    /// Pass environment variable `name=value` to the process to be launched.
+    ///
+    /// Note that sshd would reject it unless `name` is listed in
+    /// `AcceptEnv` of its config.
+    pub async fn env(&mut self, name: Cow<'_, str>, value: Cow<'_, str>) -> Result<(), Error> {
+        let recipient_channel = self.channel_ref.recipient_channel();
+
+        self.send_request(PassEnv::new(recipient_channel, name, value))
+            .await
+    }
+
+    /// Allocate a pseudo terminal for the process to be launched.
+    ///
+    /// * `term` - value of `TERM` environment variable, e.g. `xterm`.
+    /// * `modes` - terminal modes, use [`TerminalModes::new`] if there is
+    ///   nothing to set.
+    pub async fn request_pty(
+        &mut self,
+        term: Cow<'_, str>,
+        window_size: WindowSize,
+        modes: &TerminalModes,
+    ) -> Result<(), Error> {
+        let recipient_channel = self.channel_ref.recipient_channel();
+
+        self.send_request(RequestPty::new(recipient_channel, term, window_size, modes))
+            .await
+    }
+
+    /// Request sshd to forward the connections to the authentication agent
+    /// on remote back as `auth-agent@openssh.com` channels, which are
+    /// passed to the handler set by
+    /// [`ProxyClient::set_agent_forwarding_handler`](super::ProxyClient::set_agent_forwarding_handler).
+    pub async fn request_agent_forwarding(&mut self) -> Result<(), Error> {
+        let recipient_channel = self.channel_ref.recipient_channel();
+
+        self.send_request(RequestAgentForwarding::new(recipient_channel))
+            .await
+    }
+
+    /// Request sshd to forward the connections to the X11 display on remote
+    /// back as `x11` channels, which are passed to the handler set by
+    /// [`ProxyClient::set_x11_forwarding_handler`](super::ProxyClient::set_x11_forwarding_handler).
+    ///
+    /// * `single_connection` - only forward a single connection.
+    /// * `auth_protocol` - X11 authentication protocol, e.g. `MIT-MAGIC-COOKIE-1`.
+    /// * `auth_cookie` - X11 authentication cookie encoded in hexadecimal.
+    /// * `screen_number` - X11 screen number.
+    pub async fn request_x11_forwarding(
+        &mut self,
+        single_connection: bool,
+        auth_protocol: Cow<'_, str>,
+        auth_cookie: Cow<'_, str>,
+        screen_number: u32,
+    ) -> Result<(), Error> {
+        let recipient_channel = self.channel_ref.recipient_channel();
+
+        self.send_request(RequestX11Forwarding::new(
+            recipient_channel,
+            single_connection,
+            auth_protocol,
+            auth_cookie,
+            screen_number,
+        ))
+        .await
+    }
+
+    /// Launch the default shell of the user on remote.
+    pub async fn shell(mut self) -> Result<Child, Error> {
+        let recipient_channel = self.channel_ref.recipient_channel();
+
+        self.send_request(RequestShell::new(recipient_channel))
+            .await?;
+
+        Ok(self.into_child())
+    }
+
+    /// Register a receiver of extended data `data_type`, which must be
+    /// called before launching the process so that no data is missed.
+    ///
+    /// Extended data that is not registered is discarded.
+    ///
+    /// Returns `None` if `data_type` is stderr (use [`Child::stderr`]
+    /// instead) or is already registered.
+    pub fn register_extended_data(&mut self, data_type: u32) -> Option<ChannelOutput> {
+        if data_type == SSH_EXTENDED_DATA_STDERR {
+            return None;
+        }
+
+        let channel = self
+            .channel_ref
+            .channel_data
+            .register_extended_data(data_type)?;
+
+        Some(ChannelOutput::new(self.channel_ref.clone(), channel))
+    }
+
+    /// Execute `cmd` on remote.
+    pub async fn exec(mut self, cmd: Cow<'_, NonZeroByteSlice>) -> Result<Child, Error> {
+        let recipient_channel = self.channel_ref.recipient_channel();
+
+        self.send_request(ExecCmd::new(recipient_channel, cmd))
+            .await?;
+
+        Ok(self.into_child())
+    }
+
+    /// Launch `subsystem` on remote, e.g. `sftp`.
+    pub async fn subsystem(mut self, subsystem: Cow<'_, str>) -> Result<Child, Error> {
+        let recipient_channel = self.channel_ref.recipient_channel();
+
+        self.send_request(RequestSubsystem::new(recipient_channel, subsystem))
+            .await?;
+
+        Ok(self.into_child())
+    }
+
+    fn into_child(self) -> Child {
+        let channel_ref = self.channel_ref;
+        let channel_data = &channel_ref.channel_data;
+
+        let stdout = channel_data
+            .rx
+            .clone()
+            .map(|rx| ChannelOutput::new(channel_ref.clone(), rx));
+        let stderr = channel_data
+            .stderr
+            .clone()
+            .map(|stderr| ChannelOutput::new(channel_ref.clone(), stderr));
+
+        Child {
+            stdin: Some(ChannelInput::new(channel_ref.clone(), self.max_packet_size)),
+            stdout,
+            stderr,
+            channel_ref,
+        }
+    }
+}
+
+/// Process launched on remote.
+///
+/// The channel is closed once `Child` itself and all of `stdin`, `stdout`
+/// and `stderr` are dropped.
+#[derive(Debug)]
+pub struct Child {
+    /// Dropping it would send eof to the remote process.
+    pub stdin: Option<ChannelInput>,
+    pub stdout: Option<ChannelOutput>,
+    pub stderr: Option<ChannelOutput>,
+
+    channel_ref: ChannelRef,
+}
+
+impl Child {
+    /// Send the request without waiting for sshd to reply.
+    fn send_request_no_reply<T: Serialize>(
+        &self,
+        request: Request<ChannelRequest<T>>,
+    ) -> Result<(), Error> {
+        let mut buffer = BytesMut::new();
+        request.serialize_with_header(&mut buffer, 0)?;
+
+        self.channel_ref
+            .shared_data
+            .get_write_channel()
+            .push_bytes(buffer.freeze());
+
+        Ok(())
+    }
+
+    /// Id of the session channel the process is launched on.
+    pub fn channel_id(&self) -> u32 {
+        self.channel_ref.channel_id()
+    }
+
+    /// Notify the remote process that the size of the terminal
+    /// allocated by [`Session::request_pty`] has changed.
+    ///
+    /// sshd does not reply to this request, so it returns as soon
+    /// as the request is queued.
+    pub fn window_change(&self, window_size: WindowSize) -> Result<(), Error> {
+        let recipient_channel = self.channel_ref.recipient_channel();
+
+        self.send_request_no_reply(WindowChange::new(recipient_channel, window_size))
+    }
+
+    /// Send `signal` to the remote process.
+    ///
+    /// sshd does not reply to this request, so it returns as soon
+    /// as the request is queued. Use [`Child::wait`] to find out
+    /// whether the process is terminated.
+    pub fn signal(&self, signal: SignalName) -> Result<(), Error> {
+        let recipient_channel = self.channel_ref.recipient_channel();
+
+        self.send_request_no_reply(SendSignal::new(recipient_channel, signal))
+    }
+
+    /// Wait for the remote process to exit.
+    ///
+    /// `stdin` is dropped before waiting, which sends eof to the remote
+    /// process.
+    ///
+    /// Take `stdout` and `stderr` out before calling this function
+    /// if you want to read them, otherwise they are dropped and
+    /// their output is discarded.
+    ///
+    /// Returns `Ok(None)` if sshd closes the channel without sending
+    /// the exit status.
+    ///
+    /// Returns [`Error::ConnectionBroken`] if the connection to sshd is
+    /// broken before the exit status is received, or an IO error of kind
+    /// [`std::io::ErrorKind::ConnectionAborted`] if the channel is aborted by
+    /// [`ProxyClient::close_with`](crate::ProxyClient::close_with).
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe.
+    pub async fn wait(mut self) -> Result<Option<ExitStatus>, Error> {
+        drop(self.stdin.take());
+        drop(self.stdout.take());
+        drop(self.stderr.take());
+
+        let shared_data = &self.channel_ref.shared_data;
+        let cancellation_token = shared_data.get_cancellation_token();
+
+        tokio::select! {
+            biased;
+
+            exit_status = self.channel_ref.channel_data.state.wait_for_process_exit() => {
+                exit_status.map_err(|ChannelAborted| shared_data.get_terminal_error())
+            }
+            _ = cancellation_token.cancelled() => Err(shared_data.get_terminal_error()),
+        }
+    }
+}
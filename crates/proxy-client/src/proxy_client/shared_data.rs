@@ -1,13 +1,29 @@
-use std::sync::Arc;
+use std::{
+    io,
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicBool, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+};
 
-use tokio::sync::Notify;
+use tokio::sync::{Notify, Semaphore};
 use tokio_util::sync::CancellationToken;
 
 use crate::{
-    proxy_client::channel::{ChannelData, MpscBytesChannel},
+    proxy_client::{
+        channel::{ChannelConfig, ChannelData, MpscBytesChannel},
+        forwarding_handlers::ForwardingHandlers,
+        global_requests::GlobalRequests,
+        remote_forward::RemoteForwards,
+    },
     Error,
 };
 
+/// Number of channels per bucket of the arena.
+///
+/// The arena grows by buckets on demand, so it does not limit the
+/// number of concurrent channels.
 const LEN: usize = 64;
 const BITARRAY_LEN: usize = LEN / (usize::BITS as usize);
 
@@ -18,14 +34,62 @@ pub(super) type ChannelDataArenaArc = concurrent_arena::ArenaArc<ChannelData, BI
 pub(super) struct SharedData(Arc<SharedDataInner>);
 
 impl SharedData {
+    /// * `channel_config` - default configuration of channels.
+    /// * `max_channels` - max number of concurrent channels, `None` if
+    ///   unlimited.
+    pub(super) fn new(channel_config: ChannelConfig, max_channels: Option<NonZeroU32>) -> Self {
+        Self(Arc::new(SharedDataInner {
+            channel_config,
+            channel_limit: max_channels
+                .map(|max_channels| Arc::new(Semaphore::new(max_channels.get() as usize))),
+            ..Default::default()
+        }))
+    }
+
+    pub(super) fn get_channel_config(&self) -> &ChannelConfig {
+        &self.0.channel_config
+    }
+
     pub(super) fn get_write_channel(&self) -> &MpscBytesChannel {
         &self.0.write_channel
     }
 
-    pub(super) fn insert_channel_data(&self, channel_data: ChannelData) -> ChannelDataArenaArc {
+    /// Insert `channel_data`, waiting for other channels to be closed
+    /// if the max number of concurrent channels is reached.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is cancellation safe.
+    pub(super) async fn insert_channel_data(
+        &self,
+        mut channel_data: ChannelData,
+    ) -> ChannelDataArenaArc {
+        if let Some(channel_limit) = &self.0.channel_limit {
+            let permit = channel_limit
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("Semaphore is never closed");
+
+            channel_data.channel_permit = Some(permit);
+        }
+
         self.0.channel_data_arena.insert(channel_data)
     }
 
+    /// Insert `channel_data`, returns `None` if the max number of
+    /// concurrent channels is reached.
+    pub(super) fn try_insert_channel_data(
+        &self,
+        mut channel_data: ChannelData,
+    ) -> Option<ChannelDataArenaArc> {
+        if let Some(channel_limit) = &self.0.channel_limit {
+            channel_data.channel_permit = Some(channel_limit.clone().try_acquire_owned().ok()?);
+        }
+
+        Some(self.0.channel_data_arena.insert(channel_data))
+    }
+
     pub(super) fn remove_channel_data(&self, slot: u32) -> Result<ChannelDataArenaArc, Error> {
         self.0
             .channel_data_arena
@@ -40,13 +104,68 @@ impl SharedData {
             .ok_or(Error::InvalidRecipientChannel(slot))
     }
 
+    /// Stop accepting channels opened by sshd and request the read
+    /// task to close all open channels.
+    pub(super) fn start_closing(&self) {
+        self.0.is_closing.store(true, Relaxed);
+        self.0.close_requested_notifier.notify_one();
+    }
+
+    pub(super) fn is_closing(&self) -> bool {
+        self.0.is_closing.load(Relaxed)
+    }
+
+    pub(super) fn get_close_requested_notifier(&self) -> &Notify {
+        &self.0.close_requested_notifier
+    }
+
     pub(super) fn get_read_task_shutdown_notifier(&self) -> &Notify {
         &self.0.read_task_shutdown_notifier
     }
 
+    /// Record `err` that fails the read or write task, which would
+    /// be returned to all channels still open.
+    ///
+    /// Only the first error is recorded, since the other task would
+    /// fail due to it.
+    ///
+    /// Must be called before the cancellation token is cancelled.
+    pub(super) fn set_terminal_error(&self, err: Error) -> Arc<Error> {
+        let err = Arc::new(err);
+
+        let mut guard = self.0.terminal_error.lock().unwrap();
+        if guard.is_none() {
+            *guard = Some(err.clone());
+        }
+
+        err
+    }
+
+    /// Return the error for channels aborted since the cancellation
+    /// token is cancelled.
+    pub(super) fn get_terminal_error(&self) -> Error {
+        match &*self.0.terminal_error.lock().unwrap() {
+            Some(err) => Error::ConnectionBroken(err.clone()),
+            // Cancelled by `ProxyClient::close_with`
+            None => io::Error::from(io::ErrorKind::ConnectionAborted).into(),
+        }
+    }
+
     pub(super) fn get_cancellation_token(&self) -> &CancellationToken {
         &self.0.cancellation_token
     }
+
+    pub(super) fn get_global_requests(&self) -> &GlobalRequests {
+        &self.0.global_requests
+    }
+
+    pub(super) fn get_remote_forwards(&self) -> &RemoteForwards {
+        &self.0.remote_forwards
+    }
+
+    pub(super) fn get_forwarding_handlers(&self) -> &ForwardingHandlers {
+        &self.0.forwarding_handlers
+    }
 }
 
 impl Drop for SharedData {
@@ -73,7 +192,23 @@ struct SharedDataInner {
     write_channel: MpscBytesChannel,
     channel_data_arena: ChannelDataArena,
 
+    /// Limit the number of concurrent channels, `None` if unlimited.
+    channel_limit: Option<Arc<Semaphore>>,
+
     read_task_shutdown_notifier: Notify,
 
+    /// Set once `ProxyClient::close_with` is called.
+    is_closing: AtomicBool,
+    close_requested_notifier: Notify,
+
     cancellation_token: CancellationToken,
+    terminal_error: Mutex<Option<Arc<Error>>>,
+
+    global_requests: GlobalRequests,
+
+    remote_forwards: RemoteForwards,
+
+    forwarding_handlers: ForwardingHandlers,
+
+    channel_config: ChannelConfig,
 }
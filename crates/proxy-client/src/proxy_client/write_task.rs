@@ -1,7 +1,7 @@
-use std::{num::NonZeroUsize, pin::Pin};
+use std::{num::NonZeroUsize, pin::Pin, sync::Arc};
 
 use scopeguard::defer;
-use tokio::{io::AsyncWrite, pin, spawn, task::JoinHandle};
+use tokio::{io::AsyncWrite, pin, select, spawn, task::JoinHandle};
 use tokio_io_utility::{write_all_bytes, ReusableIoSlices};
 
 use crate::{proxy_client::SharedData, Error};
@@ -10,20 +10,31 @@ pub(super) fn create_write_task<W>(
     tx: W,
     shared_data: SharedData,
     reusable_io_slice_cap: NonZeroUsize,
-) -> JoinHandle<Result<(), Error>>
+) -> JoinHandle<Result<(), Arc<Error>>>
 where
     W: AsyncWrite + Send + 'static,
 {
     spawn(async move {
         pin!(tx);
 
-        create_write_task_inner(tx, shared_data, reusable_io_slice_cap).await
+        // Cancel on panic or error, after the error is recorded.
+        let cancellation_guard = shared_data.get_cancellation_token().clone().drop_guard();
+
+        let res = create_write_task_inner(tx, &shared_data, reusable_io_slice_cap)
+            .await
+            .map_err(|err| shared_data.set_terminal_error(err));
+
+        if res.is_ok() {
+            cancellation_guard.disarm();
+        }
+
+        res
     })
 }
 
 async fn create_write_task_inner(
     mut tx: Pin<&mut (dyn AsyncWrite + Send)>,
-    shared_data: SharedData,
+    shared_data: &SharedData,
     reusable_io_slice_cap: NonZeroUsize,
 ) -> Result<(), Error> {
     let write_channel = shared_data.get_write_channel();
@@ -35,19 +46,28 @@ async fn create_write_task_inner(
         shared_data.get_read_task_shutdown_notifier().notify_one();
     }
 
-    let cancellation_guard = shared_data.get_cancellation_token().clone().drop_guard();
+    let cancellation_token = shared_data.get_cancellation_token();
 
-    loop {
-        write_channel.wait_for_data(&mut buffer).await;
-        if buffer.is_empty() {
-            // Eof
-            break;
+    let write_all = async {
+        loop {
+            write_channel.wait_for_data(&mut buffer).await;
+            if buffer.is_empty() {
+                // Eof
+                break Ok::<_, Error>(());
+            }
+
+            write_all_bytes(tx.as_mut(), &mut buffer, &mut reusable_io_slice).await?;
         }
+    };
 
-        write_all_bytes(tx.as_mut(), &mut buffer, &mut reusable_io_slice).await?;
-    }
+    select! {
+        biased;
 
-    cancellation_guard.disarm();
+        // Data not yet written is discarded.
+        _ = cancellation_token.cancelled() => (),
+
+        res = write_all => res?,
+    }
 
     Ok(())
 }
@@ -31,7 +31,7 @@ pub(crate) struct DataTransfer {
 impl DataTransfer {
     fn new(recipient_channel: u32, data_len: u32) -> Request<Self> {
         Request::new(
-            SSH_MSG_CHANNEL_WINDOW_ADJUST,
+            SSH_MSG_CHANNEL_DATA,
             Self {
                 recipient_channel,
                 data_len,
@@ -1,4 +1,4 @@
-use super::Request;
+use super::{serialize_bool, Request};
 
 mod open_channel;
 pub(crate) use open_channel::*;
@@ -11,3 +11,6 @@ pub(crate) use closing_channel::*;
 
 mod request;
 pub(crate) use request::*;
+
+mod pty;
+pub use pty::{TerminalModes, WindowSize};
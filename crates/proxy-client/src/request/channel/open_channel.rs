@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 use serde::Serialize;
 
 use super::Request;
@@ -52,3 +54,119 @@ impl Session {
         )
     }
 }
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct DirectTcpIp<'a> {
+    host_to_connect: Cow<'a, str>,
+    port_to_connect: u32,
+    originator_ip_address: Cow<'a, str>,
+    originator_port: u32,
+}
+
+impl<'a> DirectTcpIp<'a> {
+    pub(crate) fn new(
+        sender_channel: u32,
+        initial_windows_size: u32,
+        max_packet_size: u32,
+        host_to_connect: Cow<'a, str>,
+        port_to_connect: u32,
+        originator_ip_address: Cow<'a, str>,
+        originator_port: u32,
+    ) -> Request<OpenChannel<Self>> {
+        OpenChannel::new(
+            &"direct-tcpip",
+            sender_channel,
+            initial_windows_size,
+            max_packet_size,
+            Self {
+                host_to_connect,
+                port_to_connect,
+                originator_ip_address,
+                originator_port,
+            },
+        )
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct DirectStreamLocal<'a> {
+    socket_path: Cow<'a, [u8]>,
+    reserved0: &'static str,
+    reserved1: u32,
+}
+
+impl<'a> DirectStreamLocal<'a> {
+    pub(crate) fn new(
+        sender_channel: u32,
+        initial_windows_size: u32,
+        max_packet_size: u32,
+        socket_path: Cow<'a, [u8]>,
+    ) -> Request<OpenChannel<Self>> {
+        OpenChannel::new(
+            &"direct-streamlocal@openssh.com",
+            sender_channel,
+            initial_windows_size,
+            max_packet_size,
+            Self {
+                socket_path,
+                reserved0: "",
+                reserved1: 0,
+            },
+        )
+    }
+}
+
+/// Confirm a channel opened by sshd.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub(crate) struct ChannelOpenConfirmation {
+    recipient_channel: u32,
+    sender_channel: u32,
+    initial_windows_size: u32,
+    max_packet_size: u32,
+}
+
+impl ChannelOpenConfirmation {
+    pub(crate) fn new(
+        recipient_channel: u32,
+        sender_channel: u32,
+        initial_windows_size: u32,
+        max_packet_size: u32,
+    ) -> Request<Self> {
+        Request::new(
+            SSH_MSG_CHANNEL_OPEN_CONFIRMATION,
+            Self {
+                recipient_channel,
+                sender_channel,
+                initial_windows_size,
+                max_packet_size,
+            },
+        )
+    }
+}
+
+/// Refuse a channel opened by sshd.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub(crate) struct ChannelOpenFailure {
+    recipient_channel: u32,
+    reason_code: u32,
+    description: &'static str,
+    language_tag: &'static str,
+}
+
+impl ChannelOpenFailure {
+    pub(crate) fn new(
+        recipient_channel: u32,
+        reason_code: u32,
+        description: &'static str,
+    ) -> Request<Self> {
+        Request::new(
+            SSH_MSG_CHANNEL_OPEN_FAILURE,
+            Self {
+                recipient_channel,
+                reason_code,
+                description,
+                language_tag: "",
+            },
+        )
+    }
+}
@@ -0,0 +1,64 @@
+use serde::{Serialize, Serializer};
+
+/// Terminal dimensions used by `pty-req` and `window-change`.
+///
+/// The character/row dimensions override the pixel dimensions
+/// when nonzero.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct WindowSize {
+    pub width_chars: u32,
+    pub height_rows: u32,
+    pub width_pixels: u32,
+    pub height_pixels: u32,
+}
+
+/// Encoded terminal modes passed in `pty-req`, as specified in
+/// [RFC 4254 section 8](https://www.rfc-editor.org/rfc/rfc4254#section-8).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TerminalModes(Vec<u8>);
+
+/// Indicates end of options.
+const TTY_OP_END: u8 = 0;
+
+impl Default for TerminalModes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TerminalModes {
+    /// Create empty terminal modes, sshd would use its defaults.
+    pub fn new() -> Self {
+        Self(vec![TTY_OP_END])
+    }
+
+    /// Set mode `opcode` (e.g. `53` for `ECHO`) to `argument`.
+    ///
+    /// # Panics
+    ///
+    /// If `opcode` is not in range `1..160`, since opcodes out of this range
+    /// either end the modes or are not followed by an `u32` argument.
+    pub fn set(&mut self, opcode: u8, argument: u32) -> &mut Self {
+        assert!(
+            (1..160).contains(&opcode),
+            "Invalid terminal mode opcode {}",
+            opcode
+        );
+
+        // Keep TTY_OP_END at the end.
+        self.0.pop();
+
+        self.0.push(opcode);
+        self.0.extend_from_slice(&argument.to_be_bytes());
+
+        self.0.push(TTY_OP_END);
+
+        self
+    }
+}
+
+impl Serialize for TerminalModes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
@@ -2,13 +2,14 @@ use std::borrow::Cow;
 
 use serde::Serialize;
 
-use super::Request;
-use crate::{constants::*, NonZeroByteSlice};
+use super::{serialize_bool, Request, TerminalModes, WindowSize};
+use crate::{constants::*, NonZeroByteSlice, SignalName};
 
 #[derive(Copy, Clone, Debug, Serialize)]
 pub(crate) struct ChannelRequest<T> {
     recipient_channel: u32,
     request_type: &'static &'static str,
+    #[serde(serialize_with = "serialize_bool")]
     want_reply: bool,
     request_specific_data: T,
 }
@@ -17,6 +18,7 @@ impl<T: Serialize> ChannelRequest<T> {
     fn new(
         recipient_channel: u32,
         request_type: &'static &'static str,
+        want_reply: bool,
         request_specific_data: T,
     ) -> Request<ChannelRequest<T>> {
         Request::new(
@@ -24,13 +26,19 @@ impl<T: Serialize> ChannelRequest<T> {
             Self {
                 recipient_channel,
                 request_type,
-                want_reply: true,
+                want_reply,
                 request_specific_data,
             },
         )
     }
 }
 
+impl<T> Request<ChannelRequest<T>> {
+    pub(crate) fn request_type(&self) -> &'static &'static str {
+        self.packet.request_type
+    }
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub(crate) struct PassEnv<'a> {
     name: Cow<'a, str>,
@@ -43,7 +51,7 @@ impl<'a> PassEnv<'a> {
         name: Cow<'a, str>,
         value: Cow<'a, str>,
     ) -> Request<ChannelRequest<Self>> {
-        ChannelRequest::new(recipient_channel, &"env", Self { name, value })
+        ChannelRequest::new(recipient_channel, &"env", true, Self { name, value })
     }
 }
 
@@ -55,18 +63,139 @@ impl<'a> ExecCmd<'a> {
         recipient_channel: u32,
         cmd: Cow<'a, NonZeroByteSlice>,
     ) -> Request<ChannelRequest<ExecCmd<'a>>> {
-        ChannelRequest::new(recipient_channel, &"exec", Self(cmd))
+        ChannelRequest::new(recipient_channel, &"exec", true, Self(cmd))
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct RequestSubsystem<'a>(Cow<'a, str>);
+
+impl<'a> RequestSubsystem<'a> {
+    pub(crate) fn new(
+        recipient_channel: u32,
+        subsystem: Cow<'a, str>,
+    ) -> Request<ChannelRequest<Self>> {
+        ChannelRequest::new(recipient_channel, &"subsystem", true, Self(subsystem))
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct RequestPty<'a> {
+    term: Cow<'a, str>,
+    window_size: WindowSize,
+    modes: &'a TerminalModes,
+}
+
+impl<'a> RequestPty<'a> {
+    pub(crate) fn new(
+        recipient_channel: u32,
+        term: Cow<'a, str>,
+        window_size: WindowSize,
+        modes: &'a TerminalModes,
+    ) -> Request<ChannelRequest<Self>> {
+        ChannelRequest::new(
+            recipient_channel,
+            &"pty-req",
+            true,
+            Self {
+                term,
+                window_size,
+                modes,
+            },
+        )
     }
 }
 
 #[derive(Copy, Clone, Debug, Serialize)]
-pub(crate) struct RequestSubsystem(&'static &'static str);
+pub(crate) struct RequestShell(());
 
-impl RequestSubsystem {
+impl RequestShell {
+    pub(crate) fn new(recipient_channel: u32) -> Request<ChannelRequest<Self>> {
+        ChannelRequest::new(recipient_channel, &"shell", true, Self(()))
+    }
+}
+
+/// Sent when the terminal size changes, sshd does not reply to it.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub(crate) struct WindowChange(WindowSize);
+
+impl WindowChange {
     pub(crate) fn new(
         recipient_channel: u32,
-        subsystem: &'static &'static str,
+        window_size: WindowSize,
     ) -> Request<ChannelRequest<Self>> {
-        ChannelRequest::new(recipient_channel, &"subsystem", Self(subsystem))
+        ChannelRequest::new(
+            recipient_channel,
+            &"window-change",
+            false,
+            Self(window_size),
+        )
+    }
+}
+
+/// Deliver a signal to the remote process, sshd does not reply to it.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct SendSignal(SignalName);
+
+impl SendSignal {
+    pub(crate) fn new(recipient_channel: u32, signal: SignalName) -> Request<ChannelRequest<Self>> {
+        ChannelRequest::new(recipient_channel, &"signal", false, Self(signal))
+    }
+}
+
+#[derive(Copy, Clone, Debug, Serialize)]
+pub(crate) struct RequestAgentForwarding(());
+
+impl RequestAgentForwarding {
+    pub(crate) fn new(recipient_channel: u32) -> Request<ChannelRequest<Self>> {
+        ChannelRequest::new(
+            recipient_channel,
+            &"auth-agent-req@openssh.com",
+            true,
+            Self(()),
+        )
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct RequestX11Forwarding<'a> {
+    #[serde(serialize_with = "serialize_bool")]
+    single_connection: bool,
+    auth_protocol: Cow<'a, str>,
+    auth_cookie: Cow<'a, str>,
+    screen_number: u32,
+}
+
+impl<'a> RequestX11Forwarding<'a> {
+    pub(crate) fn new(
+        recipient_channel: u32,
+        single_connection: bool,
+        auth_protocol: Cow<'a, str>,
+        auth_cookie: Cow<'a, str>,
+        screen_number: u32,
+    ) -> Request<ChannelRequest<Self>> {
+        ChannelRequest::new(
+            recipient_channel,
+            &"x11-req",
+            true,
+            Self {
+                single_connection,
+                auth_protocol,
+                auth_cookie,
+                screen_number,
+            },
+        )
+    }
+}
+
+/// Reply to channel request that is not supported.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub(crate) struct ChannelFailure {
+    recipient_channel: u32,
+}
+
+impl ChannelFailure {
+    pub(crate) fn new(recipient_channel: u32) -> Request<Self> {
+        Request::new(SSH_MSG_CHANNEL_FAILURE, Self { recipient_channel })
     }
 }
@@ -0,0 +1,119 @@
+use std::borrow::Cow;
+
+use serde::Serialize;
+
+use super::{serialize_bool, Request};
+use crate::constants::*;
+
+#[derive(Copy, Clone, Debug, Serialize)]
+pub(crate) struct GlobalRequest<T> {
+    request_name: &'static &'static str,
+    #[serde(serialize_with = "serialize_bool")]
+    want_reply: bool,
+    request_specific_data: T,
+}
+
+impl<T: Serialize> GlobalRequest<T> {
+    fn new(
+        request_name: &'static &'static str,
+        want_reply: bool,
+        request_specific_data: T,
+    ) -> Request<GlobalRequest<T>> {
+        Request::new(
+            SSH_MSG_GLOBAL_REQUEST,
+            Self {
+                request_name,
+                want_reply,
+                request_specific_data,
+            },
+        )
+    }
+}
+
+/// Reply to global request that is not supported.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub(crate) struct GlobalRequestFailure(());
+
+impl GlobalRequestFailure {
+    pub(crate) fn new() -> Request<Self> {
+        Request::new(SSH_MSG_REQUEST_FAILURE, Self(()))
+    }
+}
+
+/// Check whether sshd is alive, any reply (even failure) means it is.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub(crate) struct KeepaliveRequest(());
+
+impl KeepaliveRequest {
+    pub(crate) fn new() -> Request<GlobalRequest<Self>> {
+        GlobalRequest::new(&"keepalive@openssh.com", true, Self(()))
+    }
+}
+
+/// Ask sshd to listen on `address_to_bind:port_to_bind` and forward
+/// connections accepted back as `forwarded-tcpip` channels.
+///
+/// If `port_to_bind` is 0, sshd replies with the port allocated.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct TcpipForward<'a> {
+    address_to_bind: Cow<'a, str>,
+    port_to_bind: u32,
+}
+
+impl<'a> TcpipForward<'a> {
+    pub(crate) fn new(
+        address_to_bind: Cow<'a, str>,
+        port_to_bind: u32,
+    ) -> Request<GlobalRequest<Self>> {
+        GlobalRequest::new(
+            &"tcpip-forward",
+            true,
+            Self {
+                address_to_bind,
+                port_to_bind,
+            },
+        )
+    }
+
+    /// Cancel the forwarding without waiting for the reply.
+    pub(crate) fn cancel(
+        address_to_bind: Cow<'a, str>,
+        port_to_bind: u32,
+    ) -> Request<GlobalRequest<Self>> {
+        GlobalRequest::new(
+            &"cancel-tcpip-forward",
+            false,
+            Self {
+                address_to_bind,
+                port_to_bind,
+            },
+        )
+    }
+}
+
+/// Ask sshd to listen on unix socket `socket_path` and forward
+/// connections accepted back as `forwarded-streamlocal@openssh.com`
+/// channels.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct StreamLocalForward<'a> {
+    socket_path: Cow<'a, [u8]>,
+}
+
+impl<'a> StreamLocalForward<'a> {
+    pub(crate) fn new(socket_path: Cow<'a, [u8]>) -> Request<GlobalRequest<Self>> {
+        GlobalRequest::new(
+            &"streamlocal-forward@openssh.com",
+            true,
+            Self { socket_path },
+        )
+    }
+
+    /// Cancel the forwarding without waiting for the reply.
+    pub(crate) fn cancel(socket_path: Cow<'a, [u8]>) -> Request<GlobalRequest<Self>> {
+        GlobalRequest::new(
+            &"cancel-streamlocal-forward@openssh.com",
+            false,
+            Self { socket_path },
+        )
+    }
+}
@@ -1,11 +1,21 @@
 use bytes::BytesMut;
 use serde::Serialize;
-use ssh_format::{SerOutput, Serializer};
+use ssh_format::Serializer;
 
 use super::Error;
 
 mod channel;
 pub(crate) use channel::*;
+pub use channel::{TerminalModes, WindowSize};
+
+mod global_request;
+pub(crate) use global_request::*;
+
+/// ssh_format serializes `bool` as `u32`, while SSH connection protocol
+/// encodes `boolean` as a single byte.
+fn serialize_bool<S: serde::Serializer>(val: &bool, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u8(u8::from(*val))
+}
 
 #[derive(Copy, Clone, Debug, Serialize)]
 pub(crate) struct Request<T> {
@@ -61,29 +71,3 @@ impl<T: Serialize> Request<T> {
         res
     }
 }
-
-#[derive(Debug)]
-struct SliceOutput<'a>(&'a mut [u8], usize);
-
-impl SerOutput for SliceOutput<'_> {
-    fn extend_from_slice(&mut self, other: &[u8]) {
-        let start = self.1;
-        let end = start + other.len();
-
-        self.0[start..end].copy_from_slice(other);
-
-        self.1 = end;
-    }
-
-    fn push(&mut self, byte: u8) {
-        self.0[self.1] = byte;
-
-        self.1 += 1;
-    }
-
-    fn reserve(&mut self, additional: usize) {
-        if additional > self.0.len() {
-            panic!("The slice is not large enough!")
-        }
-    }
-}
@@ -1,4 +1,4 @@
-use std::fmt;
+use std::{convert::TryFrom, fmt};
 
 use bytes::Bytes;
 use serde::{de::Deserializer, Deserialize};
@@ -8,7 +8,9 @@ use crate::{constants::*, response::deserialize, Error};
 #[derive(Copy, Clone, Debug)]
 pub(crate) enum ExtendedDataType {
     Stderr,
-    Unknown,
+    /// Extended data type other than stderr, which is only delivered
+    /// if registered via `Session::register_extended_data`.
+    Other(u32),
 }
 impl<'de> Deserialize<'de> for ExtendedDataType {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
@@ -18,7 +20,7 @@ impl<'de> Deserialize<'de> for ExtendedDataType {
 
         Ok(match code {
             SSH_EXTENDED_DATA_STDERR => Stderr,
-            _ => Unknown,
+            code => Other(code),
         })
     }
 }
@@ -30,6 +32,18 @@ impl fmt::Display for ExtendedDataType {
 
 impl ExtendedDataType {
     pub(in crate::response) fn from_bytes(bytes: Bytes) -> Result<(Self, Bytes), Error> {
-        Ok((deserialize(&bytes)?, bytes.slice(4..)))
+        Ok((deserialize(&bytes)?, data_from_bytes(bytes.slice(4..))?))
     }
 }
+
+/// Extract `string data` from `bytes` without copying.
+pub(in crate::response) fn data_from_bytes(bytes: Bytes) -> Result<Bytes, Error> {
+    let len: u32 = deserialize(&bytes)?;
+    let end = usize::try_from(len)
+        .ok()
+        .and_then(|len| len.checked_add(4))
+        .filter(|end| *end <= bytes.len())
+        .ok_or(Error::InvalidResponse(&"Data length exceeds the packet"))?;
+
+    Ok(bytes.slice(4..end))
+}
@@ -1,23 +1,35 @@
 use std::borrow::Cow;
 
 use compact_str::CompactString;
-use serde::{de::Deserializer, Deserialize};
+use serde::{de::Deserializer, Deserialize, Serialize, Serializer};
 
-use crate::error::ErrMsg;
+use crate::{error::ErrMsg, response::deserialize_bool};
 
-#[derive(Copy, Clone, Debug, Deserialize)]
-#[repr(transparent)]
-pub(crate) struct ExitStatus(pub u32);
+/// Exit status of the remote process.
+#[derive(Clone, Debug)]
+pub enum ExitStatus {
+    /// The process exited with the exit code.
+    Exited(u32),
+
+    /// The process was terminated by a signal.
+    Killed(ExitSignal),
+}
 
+/// The remote process was terminated by a signal.
 #[derive(Clone, Debug, Deserialize)]
-pub(crate) struct ExitSignal {
+pub struct ExitSignal {
+    /// Signal that killed the process.
     pub signal_name: SignalName,
+    /// Whether a core dump is generated.
+    #[serde(deserialize_with = "deserialize_bool")]
     pub core_dumped: bool,
+    /// Error message and its language tag.
     pub err_msg: ErrMsg,
 }
 
-#[derive(Clone, Debug)]
-pub(crate) enum SignalName {
+/// Name of the signal, without the "SIG" prefix.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum SignalName {
     Abrt,
     Alrm,
     Fpe,
@@ -43,6 +55,37 @@ pub(crate) enum SignalName {
     /// by "config.guess".
     Extension(CompactString),
 }
+
+impl SignalName {
+    /// Return the name used in the SSH connection protocol.
+    pub fn as_str(&self) -> &str {
+        use SignalName::*;
+
+        match self {
+            Abrt => "ABRT",
+            Alrm => "ALRM",
+            Fpe => "FPE",
+            Hup => "HUP",
+            Ill => "ILL",
+            Int => "INT",
+            Kill => "KILL",
+            Pipe => "PIPE",
+            Quit => "QUIT",
+            Segv => "SEGV",
+            Term => "TERM",
+            Usr1 => "USR1",
+            Usr2 => "USR2",
+            Extension(signal) => signal,
+        }
+    }
+}
+
+impl Serialize for SignalName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
 impl<'de> Deserialize<'de> for SignalName {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         use SignalName::*;
@@ -5,7 +5,7 @@ use serde::Deserialize;
 use crate::Error;
 
 mod exit_status;
-pub(crate) use exit_status::*;
+pub use exit_status::{ExitSignal, ExitStatus, SignalName};
 
 mod data;
 pub(crate) use data::*;
@@ -37,7 +37,31 @@ pub(crate) struct ChannelOpen {
 }
 
 impl ChannelOpen {
-    pub(super) fn from_bytes(bytes: Bytes) -> Result<(Self, Bytes), Error> {
+    pub(in crate::response) fn from_bytes(bytes: Bytes) -> Result<(Self, Bytes), Error> {
         from_bytes_with_data(&bytes)
     }
 }
+
+/// Channel type specific data of `forwarded-tcpip`.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct ForwardedTcpIpData {
+    pub(crate) connected_address: CompactString,
+    pub(crate) connected_port: u32,
+    pub(crate) originator_address: CompactString,
+    pub(crate) originator_port: u32,
+}
+
+/// Channel type specific data of `forwarded-streamlocal@openssh.com`.
+///
+/// The reserved field following `socket_path` is ignored.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct ForwardedStreamLocalData {
+    pub(crate) socket_path: Bytes,
+}
+
+/// Channel type specific data of `x11`.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct X11Data {
+    pub(crate) originator_address: CompactString,
+    pub(crate) originator_port: u32,
+}
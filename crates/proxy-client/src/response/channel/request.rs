@@ -5,10 +5,7 @@ use serde::Deserialize;
 use ssh_format::from_bytes;
 
 use crate::{
-    response::{
-        channel::{ExitSignal, ExitStatus},
-        deserialize,
-    },
+    response::{channel::ExitSignal, deserialize, deserialize_bool},
     Error,
 };
 
@@ -16,14 +13,24 @@ use crate::{
 struct ChannelRequestHeader<'a> {
     #[serde(borrow)]
     pub(crate) request_type: Cow<'a, str>,
+    #[serde(deserialize_with = "deserialize_bool")]
     pub(crate) want_reply: bool,
 }
 
 #[derive(Clone, Debug)]
 pub(crate) enum ChannelRequest {
-    StatusCode(ExitStatus),
+    StatusCode(u32),
     KilledBySignal(ExitSignal),
-    Unknown,
+
+    /// `eow@openssh.com`, sent once sshd can no longer write to
+    /// the stdin of the process.
+    EndOfWrite,
+
+    /// Unsupported request, which must be replied with failure
+    /// if `want_reply` is `true`.
+    Unknown {
+        want_reply: bool,
+    },
 }
 
 impl ChannelRequest {
@@ -34,7 +41,10 @@ impl ChannelRequest {
         Ok(match header.request_type.as_ref() {
             "exit-status" => StatusCode(deserialize(data)?),
             "exit-signal" => KilledBySignal(deserialize(data)?),
-            _ => Unknown,
+            "eow@openssh.com" => EndOfWrite,
+            _ => Unknown {
+                want_reply: header.want_reply,
+            },
         })
     }
 }
@@ -1,5 +1,7 @@
+use std::borrow::Cow;
+
 use bytes::Bytes;
-use serde::Deserialize;
+use serde::{de::Deserializer, Deserialize};
 use strum::IntoStaticStr;
 
 use crate::{
@@ -14,21 +16,41 @@ where
     Ok(ssh_format::from_bytes(s)?.0)
 }
 
+/// ssh_format deserializes `bool` from `u32`, while SSH connection protocol
+/// encodes `boolean` as a single byte.
+fn deserialize_bool<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    Ok(<u8 as Deserialize>::deserialize(deserializer)? != 0)
+}
+
 mod channel;
 pub(crate) use channel::*;
+pub use channel::{ExitSignal, ExitStatus, SignalName};
 
 #[derive(Clone, Debug, IntoStaticStr)]
+#[allow(clippy::enum_variant_names)]
 pub(crate) enum Response {
+    /// Global request sent by sshd, e.g. `keepalive@openssh.com`.
+    ///
+    /// None of them is supported, so only `want_reply` is kept.
+    GlobalRequest {
+        want_reply: bool,
+    },
+
     GlobalRequestFailure,
 
-    GlobalRequestSuccess,
+    /// Contains response specific data.
+    GlobalRequestSuccess(Bytes),
 
     ChannelResponse {
         channel_response: ChannelResponse,
         recipient_channel: u32,
     },
 
-    OpenChannelRequest,
+    OpenChannelRequest {
+        channel_open: ChannelOpen,
+        /// Channel type specific data.
+        data: Bytes,
+    },
 }
 
 impl Response {
@@ -38,9 +60,18 @@ impl Response {
         let bytes = bytes.slice(2..);
 
         match packet_type {
-            SSH_MSG_REQUEST_SUCCESS => Ok(Response::GlobalRequestSuccess),
+            SSH_MSG_GLOBAL_REQUEST => {
+                let (_request_name, want_reply): (Cow<'_, str>, u8) = deserialize(&bytes)?;
+                Ok(Response::GlobalRequest {
+                    want_reply: want_reply != 0,
+                })
+            }
+            SSH_MSG_REQUEST_SUCCESS => Ok(Response::GlobalRequestSuccess(bytes)),
             SSH_MSG_REQUEST_FAILURE => Ok(Response::GlobalRequestFailure),
-            SSH_MSG_CHANNEL_OPEN => Ok(Response::OpenChannelRequest),
+            SSH_MSG_CHANNEL_OPEN => {
+                let (channel_open, data) = ChannelOpen::from_bytes(bytes)?;
+                Ok(Response::OpenChannelRequest { channel_open, data })
+            }
             packet_type => Ok(Response::ChannelResponse {
                 recipient_channel: deserialize(&bytes)?,
                 channel_response: ChannelResponse::from_packet(packet_type, bytes.slice(4..))?,
@@ -81,7 +112,7 @@ impl ChannelResponse {
             SSH_MSG_CHANNEL_WINDOW_ADJUST => Ok(BytesAdjust {
                 bytes_to_add: deserialize(&bytes)?,
             }),
-            SSH_MSG_CHANNEL_DATA => Ok(Data(bytes)),
+            SSH_MSG_CHANNEL_DATA => Ok(Data(data_from_bytes(bytes)?)),
             SSH_MSG_CHANNEL_EXTENDED_DATA => {
                 let (data_type, data) = ExtendedDataType::from_bytes(bytes)?;
                 Ok(ExtendedData { data_type, data })
@@ -30,7 +30,7 @@ test_mux_client() {
     start_ssh_tester
 
     if [ $# -lt 1 ]; then
-        cargo test test_unordered -- --nocapture
+        cargo test --all-features test_unordered -- --nocapture
         cargo test test_request_stop_listening -- --nocapture
 
         if [ -e $ControlPath ]; then
@@ -45,6 +45,22 @@ test_mux_client() {
             echo shutdown_mux_master does not work
             exit 1
         fi
+
+        start_ssh_tester
+        cargo test test_request_terminate -- --nocapture
+
+        if [ -e $ControlPath ]; then
+            echo request_terminate does not work
+            exit 1
+        fi
+
+        start_ssh_tester
+        cargo test test_sync_terminate_mux_master -- --nocapture
+
+        if [ -e $ControlPath ]; then
+            echo terminate_mux_master does not work
+            exit 1
+        fi
     else
         cargo test "$@" -- --nocapture
     fi